@@ -0,0 +1,253 @@
+use crate::color::{Color, Colorization};
+use crate::error::{Error, Result};
+use crate::light::Light;
+use crate::motion::Shutter;
+use crate::point::Point;
+use crate::scene::Scene;
+use crate::transform::Pose;
+use crate::vector3::Vector3;
+
+/// Values that can be blended, so they can be animated.
+pub trait Lerp {
+    /// The value a fraction `t` of the way to `other`.
+    fn lerp(&self, other: &Self, t: f64) -> Self;
+}
+
+impl Lerp for f64 {
+    fn lerp(&self, other: &f64, t: f64) -> f64 {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for f32 {
+    fn lerp(&self, other: &f32, t: f64) -> f32 {
+        self + (other - self) * t as f32
+    }
+}
+
+impl Lerp for Color {
+    fn lerp(&self, other: &Color, t: f64) -> Color {
+        *self * (1.0 - t) as f32 + *other * t as f32
+    }
+}
+
+impl Lerp for Vector3 {
+    fn lerp(&self, other: &Vector3, t: f64) -> Vector3 {
+        *self * (1.0 - t) + *other * t
+    }
+}
+
+impl Lerp for Point {
+    fn lerp(&self, other: &Point, t: f64) -> Point {
+        *self + (*other - *self) * t
+    }
+}
+
+impl Lerp for Pose {
+    fn lerp(&self, other: &Pose, t: f64) -> Pose {
+        Pose::lerp(self, other, t)
+    }
+}
+
+/// How a value gets from one keyframe to the next.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    /// Eased by a cubic Bezier curve from (0, 0) to (1, 1) over (time, progress), with the
+    /// control points `p1` and `p2`, like CSS `cubic-bezier`. Times have to be in 0..1.
+    Bezier { p1: (f64, f64), p2: (f64, f64) },
+    /// Jumps to the next value when reaching it.
+    Step,
+}
+
+impl Interpolation {
+    /// Slow in and out.
+    pub fn ease() -> Interpolation {
+        Interpolation::Bezier {
+            p1: (0.42, 0.0),
+            p2: (0.58, 1.0),
+        }
+    }
+
+    /// The progress towards the next keyframe, `t` of the way there in time.
+    pub fn progress(&self, t: f64) -> f64 {
+        match *self {
+            Interpolation::Linear => t,
+            Interpolation::Step => 0.0,
+            Interpolation::Bezier { p1, p2 } => {
+                let bezier = |a: f64, b: f64, s: f64| {
+                    let r = 1.0 - s;
+                    3.0 * r * r * s * a + 3.0 * r * s * s * b + s * s * s
+                };
+                // the time is monotonic in the curve parameter, so bisect for it
+                let (mut low, mut high) = (0.0, 1.0);
+                for _ in 0..40 {
+                    let middle = 0.5 * (low + high);
+                    if bezier(p1.0, p2.0, middle) < t {
+                        low = middle;
+                    } else {
+                        high = middle;
+                    }
+                }
+                bezier(p1.1, p2.1, 0.5 * (low + high))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe<T> {
+    pub time: f64,
+    pub value: T,
+    /// Towards the next keyframe.
+    pub interpolation: Interpolation,
+}
+
+/// A value changing over time, interpolated between keyframes and held before the first and
+/// after the last one.
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    keyframes: Vec<Keyframe<T>>,
+}
+
+impl<T: Lerp + Clone> Track<T> {
+    /// Fails without keyframes, or if any of their times isn't finite.
+    pub fn keyframed(mut keyframes: Vec<Keyframe<T>>) -> Result<Track<T>> {
+        if keyframes.is_empty() {
+            return Err(Error::Invalid(
+                "A track needs at least one keyframe.".to_string(),
+            ));
+        }
+        if keyframes.iter().any(|k| !k.time.is_finite()) {
+            return Err(Error::Invalid(
+                "Keyframe times have to be finite.".to_string(),
+            ));
+        }
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(Track { keyframes })
+    }
+
+    pub fn constant(value: T) -> Track<T> {
+        Track {
+            keyframes: vec![Keyframe {
+                time: 0.0,
+                value,
+                interpolation: Interpolation::Linear,
+            }],
+        }
+    }
+
+    /// Going linearly from `from` at `start` to `to` at `end`. Fails if either time isn't
+    /// finite.
+    pub fn linear(start: f64, from: T, end: f64, to: T) -> Result<Track<T>> {
+        Track::keyframed(vec![
+            Keyframe {
+                time: start,
+                value: from,
+                interpolation: Interpolation::Linear,
+            },
+            Keyframe {
+                time: end,
+                value: to,
+                interpolation: Interpolation::Linear,
+            },
+        ])
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe<T>] {
+        &self.keyframes
+    }
+
+    pub fn value(&self, time: f64) -> T {
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return self.keyframes[0].value.clone();
+        }
+        if next == self.keyframes.len() {
+            return self.keyframes[next - 1].value.clone();
+        }
+        let (a, b) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let t = a
+            .interpolation
+            .progress((time - a.time) / (b.time - a.time));
+        a.value.lerp(&b.value, t)
+    }
+}
+
+/// The animated properties of a scene, in seconds. Element transforms are animated by
+/// wrapping the elements in `Element::Moving`, which also blurs them over the shutter.
+#[derive(Clone, Debug)]
+pub struct Animation {
+    pub frame_rate: f64,
+    /// The fraction of a frame the shutter is open for, 0 for no motion blur.
+    pub shutter: f64,
+    /// The camera position and orientation. The scale is ignored.
+    pub camera: Option<Track<Pose>>,
+    pub fov: Option<Track<f64>>,
+    /// By index into the lights of the scene.
+    pub light_intensities: Vec<(usize, Track<f32>)>,
+    /// By index into the elements of the scene, replacing their colorization.
+    pub material_colors: Vec<(usize, Track<Color>)>,
+}
+
+impl Animation {
+    pub fn new(frame_rate: f64) -> Animation {
+        Animation {
+            frame_rate,
+            shutter: 0.0,
+            camera: None,
+            fov: None,
+            light_intensities: Vec::new(),
+            material_colors: Vec::new(),
+        }
+    }
+
+    /// When `frame` starts.
+    pub fn time(&self, frame: u32) -> f64 {
+        frame as f64 / self.frame_rate
+    }
+
+    /// Set the scene up for rendering `frame`. Only the animated properties are touched, so
+    /// static geometry keeps whatever it precomputed.
+    ///
+    /// Fails if an index is out of range, or an element with an animated color is an instance
+    /// sharing its material, leaving the scene partly set up.
+    pub fn apply(&self, scene: &mut Scene, frame: u32) -> Result<()> {
+        let time = self.time(frame);
+        scene.shutter = Shutter {
+            open: time,
+            close: time + self.shutter / self.frame_rate,
+        };
+        if let Some(ref camera) = self.camera {
+            let pose = camera.value(time);
+            scene.origin = pose.translation.as_point();
+            scene.orientation = pose.rotation;
+        }
+        if let Some(ref fov) = self.fov {
+            scene.fov = fov.value(time);
+        }
+        for (index, track) in &self.light_intensities {
+            match scene.lights.get_mut(*index) {
+                Some(Light::Directional(d)) => d.intensity = track.value(time),
+                Some(Light::Spherical(s)) => s.intensity = track.value(time),
+                None => return Err(Error::Invalid(format!("There's no light {}.", index))),
+            }
+        }
+        for (index, track) in &self.material_colors {
+            let material = scene
+                .elements
+                .get_mut(*index)
+                .ok_or_else(|| Error::Invalid(format!("There's no element {}.", index)))?
+                .material_mut()
+                .ok_or_else(|| {
+                    Error::Invalid(format!(
+                        "Element {} shares its material, give it its own to animate it.",
+                        index
+                    ))
+                })?;
+            material.color = Colorization::Color(track.value(time));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use crate::color::Color;
+use crate::render::{Accumulator, Film, PixelStatistics};
+use crate::scene::Scene;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"RAYSCKPT";
+const VERSION: u32 = 1;
+// the weighted color sum, the weight, and the count, mean and squares of the statistics
+const PIXEL_BYTES: usize = 4 * 4 + 4 + 8 + 8;
+const HEADER_BYTES: usize = 8 + 4 + 8 + 8 + 4 + 4;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Write the film of a progressive render, so it can be resumed. The samplers keep no state
+/// besides the seed, with the next sample of every pixel following from its count, so that's
+/// all that is stored of them. `fingerprint` is that of the scene, see `Scene::fingerprint`.
+/// Written to a temporary file first, so a crash while writing leaves the previous checkpoint
+/// intact.
+pub(crate) fn save(film: &Film, scene: &Scene, fingerprint: u64, path: &Path) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(HEADER_BYTES + film.pixels.len() * PIXEL_BYTES);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&fingerprint.to_le_bytes());
+    bytes.extend_from_slice(&scene.seed.to_le_bytes());
+    bytes.extend_from_slice(&(film.width as u32).to_le_bytes());
+    bytes.extend_from_slice(&(film.height as u32).to_le_bytes());
+    for (pixel, statistics) in film.pixels.iter().zip(&film.statistics) {
+        for value in [
+            pixel.color.red,
+            pixel.color.green,
+            pixel.color.blue,
+            pixel.weight,
+        ]
+        .iter()
+        {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&statistics.count.to_le_bytes());
+        bytes.extend_from_slice(&statistics.mean.to_le_bytes());
+        bytes.extend_from_slice(&statistics.squares.to_le_bytes());
+    }
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, &bytes)?;
+    fs::rename(&temporary, path)
+}
+
+/// Read a checkpoint written by `save` for the same scene. Fails with `InvalidData` if the
+/// file is damaged or belongs to a different scene.
+pub(crate) fn load(scene: &Scene, fingerprint: u64, path: &Path) -> io::Result<Film> {
+    let bytes = fs::read(path)?;
+    if bytes.len() < HEADER_BYTES || &bytes[..8] != MAGIC {
+        return Err(invalid("Not a render checkpoint."));
+    }
+    let mut offset = 8;
+    let mut take = |length: usize| {
+        let slice = &bytes[offset..offset + length];
+        offset += length;
+        slice
+    };
+    let u32_at = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+    let u64_at = |b: &[u8]| u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]);
+    let f32_at = |b: &[u8]| f32::from_bits(u32_at(b));
+    let f64_at = |b: &[u8]| f64::from_bits(u64_at(b));
+
+    if u32_at(take(4)) != VERSION {
+        return Err(invalid("Unsupported checkpoint version."));
+    }
+    if u64_at(take(8)) != fingerprint || u64_at(take(8)) != scene.seed {
+        return Err(invalid("The checkpoint belongs to a different scene."));
+    }
+    let mut film = Film::new(scene);
+    let (width, height) = (u32_at(take(4)), u32_at(take(4)));
+    if width as usize != film.width || height as usize != film.height {
+        return Err(invalid("The checkpoint belongs to a different scene."));
+    }
+    if bytes.len() != HEADER_BYTES + film.pixels.len() * PIXEL_BYTES {
+        return Err(invalid("Truncated checkpoint."));
+    }
+    for (pixel, statistics) in film.pixels.iter_mut().zip(film.statistics.iter_mut()) {
+        *pixel = Accumulator {
+            color: Color {
+                red: f32_at(take(4)),
+                green: f32_at(take(4)),
+                blue: f32_at(take(4)),
+            },
+            weight: f32_at(take(4)),
+        };
+        *statistics = PixelStatistics {
+            count: u32_at(take(4)),
+            mean: f64_at(take(8)),
+            squares: f64_at(take(8)),
+        };
+    }
+    Ok(film)
+}
@@ -1,9 +1,11 @@
+use crate::point::Point;
 use crate::primitives::TextureCoordinates;
-use crate::utils::{gamma_decode, gamma_encode, wrap};
+use crate::texture::{Footprint, ImageTexture, Procedural};
+use crate::utils::{gamma_decode, gamma_encode};
 use std::ops::{Add, Div, Mul};
 use std::path::PathBuf;
 
-use image::{DynamicImage, GenericImageView, Pixel, Rgba};
+use image::{DynamicImage, Pixel, Rgba};
 
 pub struct Texture {
     pub path: PathBuf,
@@ -14,22 +16,31 @@ pub fn dummy_texture() -> DynamicImage {
     DynamicImage::new_rgb8(1, 1)
 }
 
+#[derive(Debug)]
 pub enum Colorization {
     Color(Color),
-    Texture(DynamicImage),
+    Texture(ImageTexture),
+    Procedural(Procedural),
 }
 
 impl Colorization {
-    pub fn color(&self, texture_coords: &TextureCoordinates) -> Color {
+    pub fn color(&self, texture_coords: &TextureCoordinates, hit_point: &Point) -> Color {
+        self.sample(texture_coords, hit_point, &Footprint::point())
+    }
+
+    /// Like `color`, but lets image textures filter over the given footprint.
+    pub fn sample(
+        &self,
+        texture_coords: &TextureCoordinates,
+        hit_point: &Point,
+        footprint: &Footprint,
+    ) -> Color {
         match *self {
-            Colorization::Color(ref c) => c.clone(),
+            Colorization::Color(c) => c,
 
-            Colorization::Texture(ref tex) => {
-                let tex_x = wrap(texture_coords.x, tex.width());
-                let tex_y = wrap(texture_coords.y, tex.height());
+            Colorization::Texture(ref tex) => tex.sample(texture_coords, footprint),
 
-                Color::from_rgba(tex.get_pixel(tex_x, tex_y))
-            }
+            Colorization::Procedural(ref p) => p.color(texture_coords, hit_point),
         }
     }
 }
@@ -106,11 +117,19 @@ impl Color {
         }
     }
 
+    pub fn is_finite(&self) -> bool {
+        self.red.is_finite() && self.green.is_finite() && self.blue.is_finite()
+    }
+
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.red + 0.7152 * self.green + 0.0722 * self.blue
+    }
+
     pub fn clamp(&self) -> Color {
         Color {
-            red: self.red.min(1.0).max(0.0),
-            blue: self.blue.min(1.0).max(0.0),
-            green: self.green.min(1.0).max(0.0),
+            red: self.red.clamp(0.0, 1.0),
+            blue: self.blue.clamp(0.0, 1.0),
+            green: self.green.clamp(0.0, 1.0),
         }
     }
 }
@@ -0,0 +1,223 @@
+use crate::point::Point;
+use crate::primitives::{Element, Hittable, Interval, Material, TextureCoordinates};
+use crate::ray::{Ray, RayCone};
+use crate::vector3::Vector3;
+
+// How far off the surface the probes locating a hit point start
+const PROBE_DISTANCE: f64 = 1e-4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    /// The left solid with the right one cut out of it.
+    Difference,
+}
+
+impl CsgOperation {
+    fn contains(&self, in_left: bool, in_right: bool) -> bool {
+        match *self {
+            CsgOperation::Union => in_left || in_right,
+            CsgOperation::Intersection => in_left && in_right,
+            CsgOperation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// A boolean combination of two solids, shaded with its own material.
+#[derive(Debug)]
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub left: Box<Element>,
+    pub right: Box<Element>,
+    pub material: Material,
+}
+
+impl Csg {
+    /// The leaf element whose surface the point lies on, and whether its normal has to be
+    /// flipped because it bounds a subtracted solid.
+    fn surface_at(&self, hit_point: &Point, time: f64) -> (&Element, bool) {
+        let (element, flipped, _) = self.closest_surface(hit_point, time);
+        (element, flipped)
+    }
+
+    fn closest_surface(&self, hit_point: &Point, time: f64) -> (&Element, bool, f64) {
+        let (left, left_flipped, left_error) = surface_error(&self.left, hit_point, time);
+        let (right, right_flipped, right_error) = surface_error(&self.right, hit_point, time);
+        if left_error <= right_error {
+            (left, left_flipped, left_error)
+        } else {
+            let flipped = right_flipped ^ (self.operation == CsgOperation::Difference);
+            (right, flipped, right_error)
+        }
+    }
+}
+
+/// How far the point is from the surface of the leaf element, measured by shooting a probe
+/// ray at the surface from just outside of it.
+fn surface_error<'a>(
+    element: &'a Element,
+    hit_point: &Point,
+    time: f64,
+) -> (&'a Element, bool, f64) {
+    if let Element::Csg(ref csg) = *element {
+        return csg.closest_surface(hit_point, time);
+    }
+    let normal = element.surface_normal(hit_point, time).normalize();
+    let probe = Ray {
+        origin: *hit_point + normal * PROBE_DISTANCE,
+        direction: -normal,
+        cone: RayCone::default(),
+        time,
+    };
+    let error = element
+        .hit(&probe)
+        .map(|t| (t - PROBE_DISTANCE).abs())
+        .unwrap_or(f64::INFINITY);
+    (element, false, error)
+}
+
+/// Merge two sorted interval lists with a boolean operation.
+pub fn combine(left: &[Interval], right: &[Interval], operation: CsgOperation) -> Vec<Interval> {
+    // (distance, is left, is entering)
+    let mut events: Vec<(f64, bool, bool)> = Vec::with_capacity(2 * (left.len() + right.len()));
+    for i in left {
+        events.push((i.enter, true, true));
+        events.push((i.exit, true, false));
+    }
+    for i in right {
+        events.push((i.enter, false, true));
+        events.push((i.exit, false, false));
+    }
+    events.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut result = Vec::new();
+    let (mut in_left, mut in_right) = (false, false);
+    let mut enter = 0.0;
+    for (t, is_left, entering) in events {
+        let was_inside = operation.contains(in_left, in_right);
+        if is_left {
+            in_left = entering;
+        } else {
+            in_right = entering;
+        }
+        let inside = operation.contains(in_left, in_right);
+        if !was_inside && inside {
+            enter = t;
+        } else if was_inside && !inside {
+            result.push(Interval { enter, exit: t });
+        }
+    }
+    result
+}
+
+impl Hittable for Csg {
+    fn hit(&self, ray: &Ray) -> Option<f64> {
+        self.intervals(ray).iter().find_map(|i| {
+            if i.enter >= 0.0 {
+                Some(i.enter)
+            } else if i.exit >= 0.0 && i.exit.is_finite() {
+                Some(i.exit)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn surface_normal(&self, hit_point: &Point, time: f64) -> Vector3 {
+        let (element, flipped) = self.surface_at(hit_point, time);
+        let normal = element.surface_normal(hit_point, time);
+        if flipped {
+            -normal
+        } else {
+            normal
+        }
+    }
+
+    fn texture_coordinates(&self, hit_point: &Point, time: f64) -> TextureCoordinates {
+        self.surface_at(hit_point, time)
+            .0
+            .texture_coordinates(hit_point, time)
+    }
+
+    fn tangent(&self, hit_point: &Point, time: f64) -> Vector3 {
+        self.surface_at(hit_point, time).0.tangent(hit_point, time)
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        combine(
+            &self.left.intervals(ray),
+            &self.right.intervals(ray),
+            self.operation,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(intervals: &[(f64, f64)]) -> Vec<Interval> {
+        intervals
+            .iter()
+            .map(|&(enter, exit)| Interval { enter, exit })
+            .collect()
+    }
+
+    fn combined(
+        left: &[(f64, f64)],
+        right: &[(f64, f64)],
+        operation: CsgOperation,
+    ) -> Vec<(f64, f64)> {
+        combine(&spans(left), &spans(right), operation)
+            .iter()
+            .map(|i| (i.enter, i.exit))
+            .collect()
+    }
+
+    #[test]
+    fn combines_overlapping_intervals() {
+        let (left, right) = ([(1.0, 4.0)], [(2.0, 6.0)]);
+        assert_eq!(combined(&left, &right, CsgOperation::Union), [(1.0, 6.0)]);
+        assert_eq!(
+            combined(&left, &right, CsgOperation::Intersection),
+            [(2.0, 4.0)]
+        );
+        assert_eq!(
+            combined(&left, &right, CsgOperation::Difference),
+            [(1.0, 2.0)]
+        );
+        assert_eq!(
+            combined(&right, &left, CsgOperation::Difference),
+            [(4.0, 6.0)]
+        );
+    }
+
+    #[test]
+    fn combines_disjoint_intervals() {
+        let (left, right) = ([(1.0, 2.0), (5.0, 6.0)], [(3.0, 4.0)]);
+        assert_eq!(
+            combined(&left, &right, CsgOperation::Union),
+            [(1.0, 2.0), (3.0, 4.0), (5.0, 6.0)]
+        );
+        assert_eq!(combined(&left, &right, CsgOperation::Intersection), []);
+        assert_eq!(
+            combined(&left, &right, CsgOperation::Difference),
+            [(1.0, 2.0), (5.0, 6.0)]
+        );
+    }
+
+    #[test]
+    fn cuts_a_hole_through_an_interval() {
+        let (left, right) = ([(1.0, 6.0)], [(2.0, 3.0)]);
+        assert_eq!(
+            combined(&left, &right, CsgOperation::Difference),
+            [(1.0, 2.0), (3.0, 6.0)]
+        );
+        assert_eq!(combined(&left, &right, CsgOperation::Union), [(1.0, 6.0)]);
+        assert_eq!(
+            combined(&left, &right, CsgOperation::Intersection),
+            [(2.0, 3.0)]
+        );
+    }
+}
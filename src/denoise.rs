@@ -0,0 +1,159 @@
+use crate::color::Color;
+use crate::error::{Error, Result};
+use crate::render::Framebuffer;
+use rayon::prelude::*;
+
+// The B3 spline, the smoothing kernel of the wavelet transform
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// Albedos darker than this aren't divided out, as the noise would blow up
+const MIN_ALBEDO: f32 = 0.01;
+
+/// The edge-avoiding À-Trous wavelet filter of Dammertz et al. Every iteration blurs with a
+/// 5x5 kernel spread twice as wide as the last, up to the size of the image, each tap
+/// weighted down the more its color, normal, depth and albedo differ from the center pixel.
+/// The lighting is filtered with the albedo divided out, so textures stay sharp.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Denoiser {
+    pub iterations: u32,
+    /// How different colors may be and still be blurred together, halved every iteration.
+    pub color_sigma: f32,
+    pub normal_sigma: f32,
+    /// Relative to the depth of the center pixel, per pixel of distance on screen.
+    pub depth_sigma: f32,
+    pub albedo_sigma: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            iterations: 5,
+            color_sigma: 1.0,
+            normal_sigma: 0.3,
+            depth_sigma: 0.05,
+            albedo_sigma: 0.1,
+        }
+    }
+}
+
+impl Denoiser {
+    /// The framebuffer with the color denoised. Fails if a sigma isn't positive.
+    pub fn denoise(&self, framebuffer: &Framebuffer) -> Result<Framebuffer> {
+        let sigmas = [
+            self.color_sigma,
+            self.normal_sigma,
+            self.depth_sigma,
+            self.albedo_sigma,
+        ];
+        if sigmas.iter().any(|sigma| sigma.is_nan() || *sigma <= 0.0) {
+            return Err(Error::Invalid(format!(
+                "The parameters of {:?} are out of range.",
+                self
+            )));
+        }
+        let demodulate = |(color, albedo): (&Color, &Color)| {
+            let channel = |c: f32, a: f32| if a > MIN_ALBEDO { c / a } else { c };
+            Color {
+                red: channel(color.red, albedo.red),
+                green: channel(color.green, albedo.green),
+                blue: channel(color.blue, albedo.blue),
+            }
+        };
+        let mut lighting: Vec<Color> = framebuffer
+            .color
+            .iter()
+            .zip(&framebuffer.albedo)
+            .map(demodulate)
+            .collect();
+        // wider kernels only reach past the edges
+        let size = framebuffer.width.max(framebuffer.height).max(1);
+        for iteration in 0..self.iterations.min(size.ilog2()) {
+            lighting = self.filter(framebuffer, &lighting, iteration);
+        }
+
+        let remodulate = |(color, albedo): (&Color, &Color)| {
+            let channel = |c: f32, a: f32| if a > MIN_ALBEDO { c * a } else { c };
+            Color {
+                red: channel(color.red, albedo.red),
+                green: channel(color.green, albedo.green),
+                blue: channel(color.blue, albedo.blue),
+            }
+        };
+        Ok(Framebuffer {
+            color: lighting
+                .iter()
+                .zip(&framebuffer.albedo)
+                .map(remodulate)
+                .collect(),
+            ..framebuffer.clone()
+        })
+    }
+
+    /// One iteration, with the taps `2^iteration` pixels apart.
+    fn filter(&self, guide: &Framebuffer, colors: &[Color], iteration: u32) -> Vec<Color> {
+        let (width, height) = (guide.width as i64, guide.height as i64);
+        let step = 1i64 << iteration;
+        let color_sigma = self.color_sigma / 2f32.powi(iteration as i32);
+        let difference = |a: &Color, b: &Color| {
+            let d = *a + *b * -1.0;
+            d.red * d.red + d.green * d.green + d.blue * d.blue
+        };
+
+        (0..width * height)
+            .into_par_iter()
+            .map(|p| {
+                let (x, y) = (p % width, p / width);
+                let p = p as usize;
+                let mut sum = Color {
+                    red: 0.0,
+                    green: 0.0,
+                    blue: 0.0,
+                };
+                let mut total = 0.0;
+                // a NaN or infinite center can't be compared to, so only the guides weigh
+                let center = colors[p].is_finite();
+                for (j, ky) in KERNEL.iter().enumerate() {
+                    for (i, kx) in KERNEL.iter().enumerate() {
+                        let qx = (x + (i as i64 - 2) * step).clamp(0, width - 1);
+                        let qy = (y + (j as i64 - 2) * step).clamp(0, height - 1);
+                        let q = (qy * width + qx) as usize;
+                        if !colors[q].is_finite() {
+                            continue;
+                        }
+
+                        let color = if center {
+                            difference(&colors[p], &colors[q]) / (color_sigma * color_sigma)
+                        } else {
+                            0.0
+                        };
+                        let normal = {
+                            let d = guide.normal[p] - guide.normal[q];
+                            d.length_squared() as f32 / (self.normal_sigma * self.normal_sigma)
+                        };
+                        let albedo = difference(&guide.albedo[p], &guide.albedo[q])
+                            / (self.albedo_sigma * self.albedo_sigma);
+                        let depth = match (guide.depth[p], guide.depth[q]) {
+                            (a, b) if a.is_finite() && b.is_finite() => {
+                                // depth changes in proportion to the distance on screen
+                                let distance = (((qx - x).pow(2) + (qy - y).pow(2)) as f64).sqrt();
+                                let tolerance = (a * self.depth_sigma as f64 * distance).max(1e-9);
+                                let d = ((a - b) / tolerance) as f32;
+                                d * d
+                            }
+                            (a, b) if a.is_finite() == b.is_finite() => 0.0,
+                            _ => f32::INFINITY,
+                        };
+                        let weight = kx * ky * (-(color + normal + albedo + depth)).exp();
+                        sum = sum + colors[q] * weight;
+                        total += weight;
+                    }
+                }
+                if total > 0.0 {
+                    sum * (1.0 / total)
+                } else {
+                    colors[p]
+                }
+            })
+            .collect()
+    }
+}
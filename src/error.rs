@@ -0,0 +1,51 @@
+use image::ImageError;
+use std::fmt;
+use std::io;
+
+/// Why a render failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The scene or the settings of the render can't be rendered, saying why.
+    Invalid(String),
+    /// Stopped by its `CancellationToken`.
+    Cancelled,
+    /// Reading or writing a checkpoint failed.
+    Io(io::Error),
+    /// Writing an image failed.
+    Image(ImageError),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Invalid(ref message) => write!(f, "{}", message),
+            Error::Cancelled => write!(f, "The render was cancelled."),
+            Error::Io(ref error) => write!(f, "{}", error),
+            Error::Image(ref error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Error::Io(ref error) => Some(error),
+            Error::Image(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl From<ImageError> for Error {
+    fn from(error: ImageError) -> Error {
+        Error::Image(error)
+    }
+}
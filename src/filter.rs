@@ -0,0 +1,112 @@
+use std::f64::consts::PI;
+
+/// The shape of a reconstruction filter.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FilterKind {
+    /// Every sample in reach counts the same. With a radius of half a pixel, the plain average
+    /// of the samples in the pixel.
+    #[default]
+    Box,
+    /// Falls off linearly to the radius.
+    Tent,
+    /// A Gaussian with a standard deviation of a third of the radius, shifted to reach zero
+    /// at the radius.
+    Gaussian,
+    /// The cubic of Mitchell and Netravali with B = C = 1/3, sharp with little ringing.
+    Mitchell,
+    /// A sinc windowed by a sinc as wide as the radius, the sharpest but ringing the most.
+    Lanczos,
+    /// The Blackman-Harris window, close to a Gaussian but reaching zero smoothly.
+    BlackmanHarris,
+}
+
+impl FilterKind {
+    /// A radius in pixels that suits the shape.
+    pub fn default_radius(&self) -> f64 {
+        match *self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+            FilterKind::BlackmanHarris => 2.0,
+        }
+    }
+}
+
+/// How the samples of the camera rays are weighted into the pixels around them.
+/// The weight is the product of the filter along x and y, so it's zero outside of a square.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelFilter {
+    pub kind: FilterKind,
+    /// In pixels.
+    pub radius: f64,
+}
+
+impl Default for PixelFilter {
+    fn default() -> Self {
+        PixelFilter::new(FilterKind::Box)
+    }
+}
+
+impl PixelFilter {
+    /// With the default radius of the shape.
+    pub fn new(kind: FilterKind) -> PixelFilter {
+        PixelFilter {
+            kind,
+            radius: kind.default_radius(),
+        }
+    }
+
+    /// The weight of a sample `dx`, `dy` pixels away from the pixel center.
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, x: f64) -> f64 {
+        let radius = self.radius;
+        let x = x.abs();
+        if x > radius {
+            return 0.0;
+        }
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - x / radius,
+            FilterKind::Gaussian => {
+                let sigma = radius / 3.0;
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                gaussian(x) - gaussian(radius)
+            }
+            FilterKind::Mitchell => {
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                // the cubic is defined over -2..2
+                let x = 2.0 * x / radius;
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                } else {
+                    ((-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                }
+            }
+            FilterKind::Lanczos => sinc(x) * sinc(x / radius),
+            FilterKind::BlackmanHarris => {
+                let t = PI * (x / radius + 1.0);
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
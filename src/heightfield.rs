@@ -0,0 +1,315 @@
+use crate::error::{Error, Result};
+use crate::point::Point;
+use crate::primitives::{solve_quadratic, Hittable, Material, TextureCoordinates};
+use crate::ray::Ray;
+use crate::stats::{self, Counter};
+use crate::utils;
+use crate::vector3::Vector3;
+use image::DynamicImage;
+use std::fmt;
+
+/// The lowest and highest points over a block of cells, one mip level of the range pyramid.
+struct RangeLevel {
+    width: usize,
+    depth: usize,
+    ranges: Vec<(f64, f64)>,
+}
+
+/// Terrain over the xz rectangle starting at `corner`, its heights taken from the brightness of an
+/// image. Image columns run along x and rows along z; white is `height` above the corner.
+pub struct Heightfield {
+    pub material: Material,
+    corner: Point,
+    size: Vector3,
+    columns: usize,
+    rows: usize,
+    heights: Vec<f64>,
+    normals: Vec<Vector3>,
+    /// Level 0 covers single cells, every further one 2x2 blocks of the previous.
+    levels: Vec<RangeLevel>,
+    /// Of the heights, so a debug print tells heightfields apart without listing them.
+    checksum: u64,
+}
+
+impl fmt::Debug for Heightfield {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Heightfield")
+            .field("material", &self.material)
+            .field("corner", &self.corner)
+            .field("size", &self.size)
+            .field("columns", &self.columns)
+            .field("rows", &self.rows)
+            .field("checksum", &self.checksum)
+            .finish()
+    }
+}
+
+impl Heightfield {
+    /// Fails if the image is smaller than 2x2 pixels, or the size isn't finite and positive,
+    /// `height` being allowed to be 0 for flat ground.
+    pub fn new(
+        image: &DynamicImage,
+        corner: Point,
+        width: f64,
+        depth: f64,
+        height: f64,
+        material: Material,
+    ) -> Result<Heightfield> {
+        let luma = image.to_luma16();
+        let (columns, rows) = (luma.width() as usize, luma.height() as usize);
+        if columns < 2 || rows < 2 {
+            return Err(Error::Invalid(
+                "A heightfield needs at least 2x2 samples.".to_string(),
+            ));
+        }
+        let positive = |x: f64| x > 0.0 && x.is_finite();
+        if !(positive(width) && positive(depth) && height >= 0.0 && height.is_finite()) {
+            return Err(Error::Invalid(
+                "The size of a heightfield has to be finite and positive.".to_string(),
+            ));
+        }
+        let heights = luma
+            .pixels()
+            .map(|p| p.0[0] as f64 / u16::MAX as f64 * height)
+            .collect::<Vec<f64>>();
+        let checksum = utils::checksum(heights.iter().map(|h| h.to_bits()));
+        let mut field = Heightfield {
+            material,
+            corner,
+            size: Vector3 {
+                x: width,
+                y: height,
+                z: depth,
+            },
+            columns,
+            rows,
+            heights,
+            normals: Vec::new(),
+            levels: Vec::new(),
+            checksum,
+        };
+        field.normals = (0..rows)
+            .flat_map(|z| (0..columns).map(move |x| (x, z)))
+            .map(|(x, z)| field.vertex_normal(x, z))
+            .collect();
+        field.levels = field.build_levels();
+        Ok(field)
+    }
+
+    fn spacing(&self) -> (f64, f64) {
+        (
+            self.size.x / (self.columns - 1) as f64,
+            self.size.z / (self.rows - 1) as f64,
+        )
+    }
+
+    fn height(&self, x: usize, z: usize) -> f64 {
+        self.heights[z * self.columns + x]
+    }
+
+    /// From central differences of the neighbouring samples, one-sided at the borders.
+    fn vertex_normal(&self, x: usize, z: usize) -> Vector3 {
+        let (dx, dz) = self.spacing();
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.columns - 1));
+        let (z0, z1) = (z.saturating_sub(1), (z + 1).min(self.rows - 1));
+        let slope_x = (self.height(x1, z) - self.height(x0, z)) / ((x1 - x0) as f64 * dx);
+        let slope_z = (self.height(x, z1) - self.height(x, z0)) / ((z1 - z0) as f64 * dz);
+        Vector3 {
+            x: -slope_x,
+            y: 1.0,
+            z: -slope_z,
+        }
+        .normalize()
+    }
+
+    fn build_levels(&self) -> Vec<RangeLevel> {
+        let (width, depth) = (self.columns - 1, self.rows - 1);
+        let mut ranges = Vec::with_capacity(width * depth);
+        for z in 0..depth {
+            for x in 0..width {
+                let corners = [
+                    self.height(x, z),
+                    self.height(x + 1, z),
+                    self.height(x, z + 1),
+                    self.height(x + 1, z + 1),
+                ];
+                let low = corners.iter().cloned().fold(f64::INFINITY, f64::min);
+                let high = corners.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                ranges.push((low, high));
+            }
+        }
+        let mut levels = vec![RangeLevel {
+            width,
+            depth,
+            ranges,
+        }];
+        while let Some(last) = levels.last().filter(|l| l.width > 1 || l.depth > 1) {
+            let (width, depth) = (last.width.div_ceil(2), last.depth.div_ceil(2));
+            let mut ranges = Vec::with_capacity(width * depth);
+            for z in 0..depth {
+                for x in 0..width {
+                    let mut range = (f64::INFINITY, f64::NEG_INFINITY);
+                    for (cx, cz) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        let (cx, cz) = (2 * x + cx, 2 * z + cz);
+                        if cx < last.width && cz < last.depth {
+                            let (low, high) = last.ranges[cz * last.width + cx];
+                            range = (range.0.min(low), range.1.max(high));
+                        }
+                    }
+                    ranges.push(range);
+                }
+            }
+            levels.push(RangeLevel {
+                width,
+                depth,
+                ranges,
+            });
+        }
+        levels
+    }
+
+    /// The entry and exit distances of the ray through the bounds of a block of cells.
+    fn block_span(&self, ray: &Ray, level: usize, x: usize, z: usize) -> Option<(f64, f64)> {
+        let (dx, dz) = self.spacing();
+        let cells = 1 << level;
+        let (low, high) = self.levels[level].ranges[z * self.levels[level].width + x];
+        let min = [
+            self.corner.x + (x * cells) as f64 * dx,
+            self.corner.y + low,
+            self.corner.z + (z * cells) as f64 * dz,
+        ];
+        let max = [
+            self.corner.x + (((x + 1) * cells).min(self.columns - 1)) as f64 * dx,
+            self.corner.y + high,
+            self.corner.z + (((z + 1) * cells).min(self.rows - 1)) as f64 * dz,
+        ];
+        let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+        let direction = [ray.direction.x, ray.direction.y, ray.direction.z];
+        let mut enter = 0.0f64;
+        let mut exit = f64::INFINITY;
+        for axis in 0..3 {
+            if direction[axis].abs() < 1e-12 {
+                if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (min[axis] - origin[axis]) / direction[axis];
+            let t1 = (max[axis] - origin[axis]) / direction[axis];
+            enter = enter.max(t0.min(t1));
+            exit = exit.min(t0.max(t1));
+        }
+        if enter <= exit {
+            Some((enter, exit))
+        } else {
+            None
+        }
+    }
+
+    /// Where the ray crosses the bilinear patch of a single cell between `enter` and `exit`.
+    fn cell_hit(&self, ray: &Ray, x: usize, z: usize, enter: f64, exit: f64) -> Option<f64> {
+        let (dx, dz) = self.spacing();
+        let h00 = self.height(x, z);
+        let h10 = self.height(x + 1, z);
+        let h01 = self.height(x, z + 1);
+        let h11 = self.height(x + 1, z + 1);
+        let (a, b, c, d) = (h00, h10 - h00, h01 - h00, h11 - h10 - h01 + h00);
+
+        // the ray in the cell's bilinear parameters
+        let u0 = (ray.origin.x - self.corner.x) / dx - x as f64;
+        let v0 = (ray.origin.z - self.corner.z) / dz - z as f64;
+        let y0 = ray.origin.y - self.corner.y;
+        let (du, dv, dy) = (ray.direction.x / dx, ray.direction.z / dz, ray.direction.y);
+
+        // y(t) - h(u(t), v(t)) = 0
+        let qa = -d * du * dv;
+        let qb = dy - b * du - c * dv - d * (u0 * dv + v0 * du);
+        let qc = y0 - a - b * u0 - c * v0 - d * u0 * v0;
+        let tolerance = 1e-9 * (1.0 + exit.abs());
+        let (t0, t1) = solve_quadratic(qa, qb, qc)?;
+        [t0, t1]
+            .iter()
+            .copied()
+            .find(|&t| t >= 0.0 && t >= enter - tolerance && t <= exit + tolerance)
+    }
+
+    /// The cell under the point and the position inside it, both clamped to the grid.
+    fn locate(&self, hit_point: &Point) -> (usize, usize, f64, f64) {
+        let (dx, dz) = self.spacing();
+        let gx = ((hit_point.x - self.corner.x) / dx).clamp(0.0, (self.columns - 1) as f64);
+        let gz = ((hit_point.z - self.corner.z) / dz).clamp(0.0, (self.rows - 1) as f64);
+        let x = (gx as usize).min(self.columns - 2);
+        let z = (gz as usize).min(self.rows - 2);
+        (x, z, gx - x as f64, gz - z as f64)
+    }
+}
+
+impl Hittable for Heightfield {
+    /// Descends the range pyramid nearest block first, skipping blocks the ray passes over
+    /// or that start behind the closest hit so far.
+    fn hit(&self, ray: &Ray) -> Option<f64> {
+        let top = self.levels.len() - 1;
+        let mut closest: Option<f64> = None;
+        let mut stack = Vec::new();
+        if let Some((enter, exit)) = self.block_span(ray, top, 0, 0) {
+            stack.push((top, 0, 0, enter, exit));
+        }
+        let mut visits = 0;
+        while let Some((level, x, z, enter, exit)) = stack.pop() {
+            if closest.is_some_and(|c| enter > c) {
+                continue;
+            }
+            visits += 1;
+            if level == 0 {
+                if let Some(t) = self.cell_hit(ray, x, z, enter, exit) {
+                    closest = Some(closest.map_or(t, |c| c.min(t)));
+                }
+                continue;
+            }
+            let below = &self.levels[level - 1];
+            let mut children = Vec::with_capacity(4);
+            for (cx, cz) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let (cx, cz) = (2 * x + cx, 2 * z + cz);
+                if cx < below.width && cz < below.depth {
+                    if let Some((enter, exit)) = self.block_span(ray, level - 1, cx, cz) {
+                        children.push((level - 1, cx, cz, enter, exit));
+                    }
+                }
+            }
+            // farthest first, so the nearest is popped next
+            children.sort_by(|a, b| b.3.total_cmp(&a.3));
+            stack.extend(children);
+        }
+        stats::add(Counter::HeightfieldNode, visits);
+        closest
+    }
+
+    /// Bilinearly interpolated from the normals at the samples, so the terrain shades smoothly.
+    fn surface_normal(&self, hit_point: &Point, _: f64) -> Vector3 {
+        let (x, z, u, v) = self.locate(hit_point);
+        let n = |x: usize, z: usize| self.normals[z * self.columns + x];
+        (n(x, z) * ((1.0 - u) * (1.0 - v))
+            + n(x + 1, z) * (u * (1.0 - v))
+            + n(x, z + 1) * ((1.0 - u) * v)
+            + n(x + 1, z + 1) * (u * v))
+            .normalize()
+    }
+
+    /// The image laid over the whole terrain.
+    fn texture_coordinates(&self, hit_point: &Point, _: f64) -> TextureCoordinates {
+        TextureCoordinates {
+            x: ((hit_point.x - self.corner.x) / self.size.x) as f32,
+            y: ((hit_point.z - self.corner.z) / self.size.z) as f32,
+        }
+    }
+
+    fn tangent(&self, hit_point: &Point, time: f64) -> Vector3 {
+        let normal = self.surface_normal(hit_point, time);
+        let x_axis = Vector3 {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        (x_axis - normal * normal.x).normalize()
+    }
+}
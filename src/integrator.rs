@@ -0,0 +1,166 @@
+use crate::color::Color;
+use crate::medium::Flight;
+use crate::primitives::{Hittable, Intersection, SurfaceType};
+use crate::ray::{Ray, RayCone};
+use crate::sampler::{CameraSample, Dimension};
+use crate::scene::{fresnel, in_scattered_light, shade_diffuse, Scene, BLACK};
+use crate::stats::{self, Counter};
+use crate::vector3::Vector3;
+
+/// How the light arriving along camera rays is computed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Integrator {
+    #[default]
+    /// Direct light, mirror reflection and refraction, and single scattering in media.
+    Whitted,
+    /// Random walks, adding diffuse interreflection and multiple scattering in media.
+    /// Noisy, so it needs more samples per pixel.
+    PathTracing,
+}
+
+/// The light arriving along the ray, estimated by following a single random path.
+pub fn trace_path(scene: &Scene, ray: &Ray, depth: u32, sample: &CameraSample) -> Color {
+    if depth >= scene.max_recursion {
+        stats::count(Counter::RecursionLimit);
+        return BLACK;
+    }
+    stats::reach(depth);
+    let intersection = scene.trace(ray);
+    let distance = intersection.as_ref().map(|i| i.distance);
+    let mut weight = Color {
+        red: 1.0,
+        green: 1.0,
+        blue: 1.0,
+    };
+    if let Some(medium) = scene.medium_along(ray, distance) {
+        match medium.fly(ray, distance.unwrap_or(f64::INFINITY), &mut sample.stream()) {
+            Flight::Scattered {
+                distance,
+                weight: scattered,
+                emission,
+            } => {
+                let point = ray.at(distance);
+                let next = Ray {
+                    origin: point,
+                    direction: medium
+                        .sample_phase(ray.direction, sample.get_2d(depth, Dimension::Phase)),
+                    cone: RayCone::default(),
+                    time: ray.time,
+                };
+                stats::count(Counter::ScatteredRay);
+                let direct = in_scattered_light(scene, medium, &point, ray, sample);
+                return emission
+                    + scattered * (direct + trace_path(scene, &next, depth + 1, sample));
+            }
+            Flight::Passed { weight: passed } => weight = passed,
+        }
+    }
+
+    match intersection {
+        Some(i) => weight * shade_path(scene, ray, &i, depth, sample),
+        None => BLACK,
+    }
+}
+
+fn shade_path(
+    scene: &Scene,
+    ray: &Ray,
+    intersection: &Intersection,
+    depth: u32,
+    sample: &CameraSample,
+) -> Color {
+    let element = intersection.element;
+    let hit = ray.at(intersection.distance);
+    let normal = element.shading_normal(&hit, ray.time);
+    let cone = ray.cone.at(intersection.distance);
+    let footprint = element.footprint(
+        &hit,
+        ray.time,
+        element.surface_normal(&hit, ray.time),
+        ray.direction,
+        cone.width,
+    );
+
+    let material = element.material();
+    let diffuse = || {
+        let direct = shade_diffuse(scene, element, hit, normal, &footprint, ray.time, sample);
+        let bounce = Ray {
+            origin: hit + (normal * scene.shadow_bias),
+            direction: cosine_direction(normal, sample.get_2d(depth, Dimension::Bsdf)),
+            cone: RayCone::default(),
+            time: ray.time,
+        };
+        stats::count(Counter::ScatteredRay);
+        // albedos above one would add energy with every bounce
+        let reflectance = element.color(&hit, ray.time, &footprint) * material.albedo.min(1.0);
+        direct + reflectance * trace_path(scene, &bounce, depth + 1, sample)
+    };
+
+    match material.surface {
+        SurfaceType::Diffuse => diffuse(),
+        SurfaceType::Reflective {
+            reflectivity,
+            ref roughness,
+        } => {
+            if sample.get_1d(depth, Dimension::Lobe) < reflectivity as f64 {
+                let reflection_ray = Ray::create_glossy_reflection(
+                    normal,
+                    ray.direction,
+                    hit,
+                    scene.shadow_bias,
+                    element.scalar(roughness, &hit, ray.time) as f64,
+                    sample.get_2d(depth, Dimension::Bsdf),
+                    ray.time,
+                )
+                .with_cone(cone);
+                stats::count(Counter::ReflectionRay);
+                trace_path(scene, &reflection_ray, depth + 1, sample)
+            } else {
+                diffuse()
+            }
+        }
+        SurfaceType::Refractive {
+            index,
+            transparency,
+        } => {
+            let kr = fresnel(ray.direction, normal, index) as f32;
+            let transmission_ray = Ray::create_transmission(
+                normal,
+                ray.direction,
+                hit,
+                scene.shadow_bias,
+                index,
+                ray.time,
+            );
+            let next = match transmission_ray {
+                Some(transmission) if sample.get_1d(depth, Dimension::Lobe) >= kr as f64 => {
+                    stats::count(Counter::RefractionRay);
+                    transmission
+                }
+                _ => {
+                    stats::count(Counter::ReflectionRay);
+                    Ray::create_reflection(normal, ray.direction, hit, scene.shadow_bias, ray.time)
+                }
+            };
+            trace_path(scene, &next.with_cone(cone), depth + 1, sample)
+                * transparency
+                * element.color(&hit, ray.time, &footprint)
+        }
+        SurfaceType::Boundary => {
+            let through = ray.pass_through(&hit, scene.shadow_bias).with_cone(cone);
+            trace_path(scene, &through, depth + 1, sample)
+        }
+    }
+}
+
+/// A direction around `normal` with probability proportional to the cosine of the angle to it,
+/// picked by `sample`.
+fn cosine_direction(normal: Vector3, sample: (f64, f64)) -> Vector3 {
+    let (u, v) = sample;
+    let radius = u.sqrt();
+    let phi = 2.0 * std::f64::consts::PI * v;
+    let normal = normal.normalize();
+    let (first, second) = normal.orthonormal_basis();
+    (first * (radius * phi.cos()) + second * (radius * phi.sin()) + normal * (1.0 - u).sqrt())
+        .normalize()
+}
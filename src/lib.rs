@@ -1,9 +1,26 @@
+pub mod animation;
+mod checkpoint;
 pub mod color;
+pub mod csg;
+pub mod denoise;
+pub mod error;
+pub mod filter;
+pub mod heightfield;
+pub mod integrator;
 pub mod light;
+pub mod medium;
+pub mod motion;
 pub mod point;
+pub mod post;
 pub mod primitives;
 pub mod ray;
 pub mod render;
+pub mod sampler;
 pub mod scene;
+pub mod sdf;
+pub mod stats;
+pub mod texture;
+pub mod transform;
 pub mod utils;
 pub mod vector3;
+pub mod volume;
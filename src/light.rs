@@ -2,18 +2,21 @@ use crate::color::Color;
 use crate::point::Point;
 use crate::vector3::Vector3;
 
+#[derive(Debug)]
 pub struct DirectionalLight {
     pub direction: Vector3,
     pub color: Color,
     pub intensity: f32,
 }
 
+#[derive(Debug)]
 pub struct SphericalLight {
     pub position: Point,
     pub color: Color,
     pub intensity: f32,
 }
 
+#[derive(Debug)]
 pub enum Light {
     Directional(DirectionalLight),
     Spherical(SphericalLight),
@@ -46,7 +49,7 @@ impl Light {
 
     pub fn distance(&self, hit_point: &Point) -> f64 {
         match *self {
-            Light::Directional(_) => std::f64::INFINITY,
+            Light::Directional(_) => f64::INFINITY,
             Light::Spherical(ref s) => (s.position - *hit_point).length(),
         }
     }
@@ -1,17 +1,28 @@
-use image;
 use rays::color::{dummy_texture, Color, Colorization};
+use rays::error::Error;
+use rays::filter::PixelFilter;
+use rays::integrator::Integrator;
 use rays::light::{Light, SphericalLight};
+use rays::motion::Shutter;
 use rays::point::Point;
 use rays::primitives::{Element, Material, Plane, Sphere, SurfaceType};
-use rays::render::render;
+use rays::render::{render_with_progress, CancellationToken, Progress, Sampling};
+use rays::sampler::Sampler;
 use rays::scene::Scene;
+use rays::texture::{Filter, ImageTexture, Scalar, WrapMode};
+use rays::transform::Quaternion;
 use rays::vector3::Vector3;
 
-fn main() {
+fn main() -> Result<(), Error> {
     let material = Material {
-        color: Colorization::Texture(image::open("texture.png").unwrap_or(dummy_texture())),
+        color: Colorization::Texture(ImageTexture::new(
+            &image::open("texture.png").unwrap_or_else(|_| dummy_texture()),
+            WrapMode::Repeat,
+            Filter::Trilinear,
+        )),
         albedo: 2.9,
         surface: SurfaceType::Diffuse,
+        ..Default::default()
     };
 
     let material2 = Material {
@@ -21,7 +32,11 @@ fn main() {
             blue: 0.4,
         }),
         albedo: 1.0,
-        surface: SurfaceType::Reflective { reflectivity: 0.97 },
+        surface: SurfaceType::Reflective {
+            reflectivity: 0.97,
+            roughness: Scalar::Constant(0.0),
+        },
+        ..Default::default()
     };
 
     let material3 = Material {
@@ -32,6 +47,7 @@ fn main() {
         }),
         albedo: 1.0,
         surface: SurfaceType::Diffuse,
+        ..Default::default()
     };
 
     let material4 = Material {
@@ -42,6 +58,7 @@ fn main() {
         }),
         albedo: 1.0,
         surface: SurfaceType::Diffuse,
+        ..Default::default()
     };
 
     let material5 = Material {
@@ -55,6 +72,7 @@ fn main() {
             index: 1.02,
             transparency: 0.6,
         },
+        ..Default::default()
     };
 
     let material6 = Material {
@@ -65,6 +83,7 @@ fn main() {
         }),
         albedo: 2.0,
         surface: SurfaceType::Diffuse,
+        ..Default::default()
     };
 
     let elements = vec![
@@ -84,7 +103,7 @@ fn main() {
                 z: -3.0,
             },
             radius: 1.0,
-            material: material,
+            material,
         }),
         Element::Sphere(Sphere {
             center: Point {
@@ -170,11 +189,42 @@ fn main() {
             z: 1.0,
         },
         fov: 90.0,
-        elements: elements,
-        lights: lights,
+        orientation: Quaternion::identity(),
+        elements,
+        lights,
         shadow_bias: 1E-10,
         max_recursion: 15,
+        medium: None,
+        integrator: Integrator::Whitted,
+        shutter: Shutter::default(),
+        sampler: Sampler::Independent,
+        sampling: Sampling::default(),
+        filter: PixelFilter::default(),
+        crop: None,
+        post: Vec::new(),
+        seed: 0,
+    };
+    let (img, stats) = render_with_progress(&scene, show_progress, &CancellationToken::new())?;
+    eprintln!();
+    eprintln!("{}", stats);
+    img.save("examples/1.png")?;
+    Ok(())
+}
+
+fn show_progress(progress: &Progress) {
+    const BAR_WIDTH: u64 = 40;
+    let filled = (progress.pixels_done * BAR_WIDTH / progress.pixels.max(1)) as usize;
+    let remaining = match progress.remaining {
+        Some(remaining) => format!("{}s left", remaining.as_secs()),
+        None => String::from("estimating"),
     };
-    let img = render(&scene);
-    img.save("examples/1.png").unwrap();
+    eprint!(
+        "\rpass {} [{:<width$}] {:>3}% {:.2}M samples/s, {}   ",
+        progress.pass + 1,
+        "#".repeat(filled),
+        progress.pixels_done * 100 / progress.pixels.max(1),
+        progress.samples_per_second / 1e6,
+        remaining,
+        width = BAR_WIDTH as usize
+    );
 }
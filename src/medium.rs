@@ -0,0 +1,265 @@
+use crate::color::Color;
+use crate::ray::Ray;
+use crate::vector3::Vector3;
+use crate::volume::DensityField;
+use rand::prelude::*;
+
+const BLACK: Color = Color {
+    red: 0.0,
+    green: 0.0,
+    blue: 0.0,
+};
+
+const WHITE: Color = Color {
+    red: 1.0,
+    green: 1.0,
+    blue: 1.0,
+};
+
+/// The outcome of flying through a medium.
+pub enum Flight {
+    /// Interacted at `distance`. The light scattered there is weighted by `weight`, and
+    /// `emission` estimates the light the medium gave off on the way.
+    Scattered {
+        distance: f64,
+        weight: Color,
+        emission: Color,
+    },
+    /// Made it to the end of the segment, weighted by `weight`.
+    Passed { weight: Color },
+}
+
+/// A participating medium, like fog, smoke or tinted glass.
+/// The coefficients are per unit of distance, for each color channel.
+#[derive(Clone, Debug)]
+pub struct Medium {
+    pub absorption: Color,
+    pub scattering: Color,
+    /// The Henyey-Greenstein asymmetry, from -1 (back scattering) through 0 (isotropic)
+    /// to 1 (forward scattering).
+    pub anisotropy: f64,
+    /// The light given off per unit of distance.
+    pub emission: Color,
+    /// Scales the coefficients and the emission point by point. `None` for a homogeneous medium.
+    pub density: Option<DensityField>,
+}
+
+impl Medium {
+    /// A medium that scatters `density` and absorbs nothing, like fog.
+    pub fn fog(color: Color, density: f32, anisotropy: f64) -> Medium {
+        Medium {
+            absorption: BLACK,
+            scattering: color * density,
+            anisotropy,
+            emission: BLACK,
+            density: None,
+        }
+    }
+
+    /// A heterogeneous medium, e.g. smoke, with the density of the field scaled by `density`.
+    /// `albedo` is the scattered fraction of the light interacting with it, and `emission` the
+    /// light it gives off per unit of density.
+    pub fn voxels(field: DensityField, density: f32, albedo: Color, emission: Color) -> Medium {
+        Medium {
+            absorption: Color {
+                red: 1.0 - albedo.red,
+                green: 1.0 - albedo.green,
+                blue: 1.0 - albedo.blue,
+            } * density,
+            scattering: albedo * density,
+            anisotropy: 0.0,
+            emission: emission * density,
+            density: Some(field),
+        }
+    }
+
+    pub fn extinction(&self) -> Color {
+        self.absorption + self.scattering
+    }
+
+    /// The extinction distances are sampled with, the average of the channels.
+    pub fn sampling_density(&self) -> f64 {
+        let extinction = self.extinction();
+        ((extinction.red + extinction.green + extinction.blue) / 3.0) as f64
+    }
+
+    /// An upper bound of the extinction of any channel anywhere in the medium.
+    fn majorant(&self, field: &DensityField) -> f64 {
+        let extinction = self.extinction();
+        (extinction.red.max(extinction.green).max(extinction.blue) * field.grid.max()) as f64
+    }
+
+    /// The fraction of light making it through the first `distance` of the ray.
+    /// Estimated by ratio tracking in heterogeneous media.
+    pub fn transmittance(&self, ray: &Ray, distance: f64, rng: &mut impl Rng) -> Color {
+        let extinction = self.extinction();
+        let field = match self.density {
+            Some(ref field) => field,
+            None => {
+                let channel = |sigma: f32| {
+                    if sigma <= 0.0 {
+                        1.0
+                    } else {
+                        (-(sigma as f64) * distance).exp() as f32
+                    }
+                };
+                return Color {
+                    red: channel(extinction.red),
+                    green: channel(extinction.green),
+                    blue: channel(extinction.blue),
+                };
+            }
+        };
+
+        let majorant = self.majorant(field);
+        let (mut t, end) = match field.span(ray) {
+            Some((enter, exit)) if majorant > 0.0 => (enter, exit.min(distance)),
+            _ => return WHITE,
+        };
+        let mut transmittance = WHITE;
+        loop {
+            t -= (1.0 - rng.gen::<f64>()).ln() / majorant;
+            if t >= end {
+                return transmittance;
+            }
+            let density = field.density(&ray.at(t));
+            let null = |sigma: f32| 1.0 - sigma * density / majorant as f32;
+            transmittance = transmittance
+                * Color {
+                    red: null(extinction.red),
+                    green: null(extinction.green),
+                    blue: null(extinction.blue),
+                };
+        }
+    }
+
+    /// The Henyey-Greenstein phase function, `cos_theta` being the cosine of the angle
+    /// between the directions the light travels in before and after scattering.
+    pub fn phase(&self, cos_theta: f64) -> f64 {
+        let g = self.anisotropy;
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * std::f64::consts::PI * denominator * denominator.sqrt())
+    }
+
+    /// A direction to continue in after scattering while travelling along `direction`,
+    /// distributed by the phase function, which it cancels out. `sample` picks the direction.
+    pub fn sample_phase(&self, direction: Vector3, sample: (f64, f64)) -> Vector3 {
+        let g = self.anisotropy;
+        let (u, v) = sample;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            (1.0 + g * g - s * s) / (2.0 * g)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * v;
+        let (first, second) = direction.orthonormal_basis();
+        (direction * cos_theta + (first * phi.cos() + second * phi.sin()) * sin_theta).normalize()
+    }
+
+    /// A point to gather the light scattered towards the ray origin at, along the first
+    /// `limit` of the ray. Homogeneous media always return one, as the transmittance is known.
+    pub fn sample_scattering(&self, ray: &Ray, limit: f64, rng: &mut impl Rng) -> Flight {
+        let density = self.sampling_density();
+        if self.density.is_some() || density <= 0.0 {
+            return self.fly(ray, limit, rng);
+        }
+        // truncated to the segment, so every sample lands inside it
+        let reach = 1.0 - (-density * limit).exp();
+        let u: f64 = rng.gen();
+        let distance = -(1.0 - u * reach).ln() / density;
+        let pdf = density * (-density * distance).exp() / reach;
+        let transmittance = self.transmittance(ray, distance, rng) * (1.0 / pdf) as f32;
+        Flight::Scattered {
+            distance,
+            weight: transmittance * self.scattering,
+            emission: transmittance * self.emission,
+        }
+    }
+
+    /// Sample how far light gets along the first `limit` of the ray before interacting with
+    /// the medium. Both outcomes are weighted to stay unbiased for every channel.
+    pub fn fly(&self, ray: &Ray, limit: f64, rng: &mut impl Rng) -> Flight {
+        match self.density {
+            Some(ref field) => self.delta_tracking(field, ray, limit, rng),
+            None => self.free_flight(limit, rng),
+        }
+    }
+
+    fn free_flight(&self, limit: f64, rng: &mut impl Rng) -> Flight {
+        let density = self.sampling_density();
+        if density <= 0.0 {
+            return Flight::Passed { weight: WHITE };
+        }
+        let u: f64 = rng.gen();
+        let distance = -(1.0 - u).ln() / density;
+        let extinction = self.extinction();
+        // the transmittance of each channel over that of the sampling density
+        let ratio = |sigma: f32, t: f64| ((density - sigma as f64) * t).exp() as f32;
+        if distance < limit {
+            let ratios = Color {
+                red: ratio(extinction.red, distance),
+                green: ratio(extinction.green, distance),
+                blue: ratio(extinction.blue, distance),
+            } * (1.0 / density) as f32;
+            Flight::Scattered {
+                distance,
+                weight: ratios * self.scattering,
+                emission: ratios * self.emission,
+            }
+        } else {
+            Flight::Passed {
+                weight: Color {
+                    red: ratio(extinction.red, limit),
+                    green: ratio(extinction.green, limit),
+                    blue: ratio(extinction.blue, limit),
+                },
+            }
+        }
+    }
+
+    /// Delta tracking against the majorant, with the average of the channels deciding between
+    /// real and null collisions and the weights correcting for each channel.
+    fn delta_tracking(
+        &self,
+        field: &DensityField,
+        ray: &Ray,
+        limit: f64,
+        rng: &mut impl Rng,
+    ) -> Flight {
+        let majorant = self.majorant(field);
+        let mean = self.sampling_density();
+        let (mut t, end) = match field.span(ray) {
+            Some((enter, exit)) if majorant > 0.0 && mean > 0.0 => (enter, exit.min(limit)),
+            _ => return Flight::Passed { weight: WHITE },
+        };
+        let extinction = self.extinction();
+        let mut weight = WHITE;
+        loop {
+            t -= (1.0 - rng.gen::<f64>()).ln() / majorant;
+            if t >= end {
+                return Flight::Passed { weight };
+            }
+            let density = field.density(&ray.at(t)) as f64;
+            let real = density * mean / majorant;
+            if rng.gen::<f64>() < real {
+                let scale = (1.0 / mean) as f32;
+                return Flight::Scattered {
+                    distance: t,
+                    weight: weight * self.scattering * scale,
+                    emission: weight * self.emission * scale,
+                };
+            }
+            let null = |sigma: f32| {
+                ((majorant - sigma as f64 * density) / (majorant - density * mean)) as f32
+            };
+            weight = weight
+                * Color {
+                    red: null(extinction.red),
+                    green: null(extinction.green),
+                    blue: null(extinction.blue),
+                };
+        }
+    }
+}
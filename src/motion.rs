@@ -0,0 +1,66 @@
+use crate::animation::Track;
+use crate::point::Point;
+use crate::primitives::{Element, Hittable, Instance, Interval, Material, TextureCoordinates};
+use crate::ray::Ray;
+use crate::transform::Pose;
+use crate::vector3::Vector3;
+use std::sync::Arc;
+
+/// The interval the camera shutter is open for. Every camera ray is sent at a random time in it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Shutter {
+    pub open: f64,
+    pub close: f64,
+}
+
+impl Shutter {
+    /// The time a fraction `u` into the interval.
+    pub fn time(&self, u: f64) -> f64 {
+        self.open + (self.close - self.open) * u
+    }
+}
+
+/// A pose changing over time.
+pub type Motion = Track<Pose>;
+
+/// An element moving over the shutter interval, blurred in the render.
+#[derive(Debug)]
+pub struct Moving {
+    pub element: Arc<Element>,
+    pub motion: Motion,
+    /// Overrides the material of the element when set.
+    pub material: Option<Material>,
+}
+
+impl Moving {
+    /// The element frozen at `time`.
+    fn at(&self, time: f64) -> Instance {
+        Instance {
+            element: self.element.clone(),
+            transform: self.motion.value(time).transform(),
+            material: None,
+        }
+    }
+}
+
+impl Hittable for Moving {
+    fn hit(&self, ray: &Ray) -> Option<f64> {
+        self.at(ray.time).hit(ray)
+    }
+
+    fn surface_normal(&self, hit_point: &Point, time: f64) -> Vector3 {
+        self.at(time).surface_normal(hit_point, time)
+    }
+
+    fn texture_coordinates(&self, hit_point: &Point, time: f64) -> TextureCoordinates {
+        self.at(time).texture_coordinates(hit_point, time)
+    }
+
+    fn tangent(&self, hit_point: &Point, time: f64) -> Vector3 {
+        self.at(time).tangent(hit_point, time)
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        self.at(ray.time).intervals(ray)
+    }
+}
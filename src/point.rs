@@ -1,5 +1,5 @@
 use crate::vector3::Vector3;
-use std::ops::{Add, Div, Sub};
+use std::ops::{Add, Div, Mul, Sub};
 
 #[derive(Copy, Clone, Debug)]
 #[repr(C)]
@@ -41,6 +41,17 @@ impl Div<f64> for Point {
     }
 }
 
+impl Mul<f64> for Point {
+    type Output = Point;
+    fn mul(self, other: f64) -> Point {
+        Point {
+            x: self.x * other,
+            y: self.y * other,
+            z: self.z * other,
+        }
+    }
+}
+
 impl Add<Vector3> for Point {
     type Output = Point;
     fn add(self, other: Vector3) -> Point {
@@ -0,0 +1,349 @@
+use crate::color::Color;
+use crate::sampler;
+use crate::scene::BLACK;
+use crate::utils::{gamma_decode, gamma_encode};
+use rayon::prelude::*;
+use std::f64::consts::PI;
+
+// The binomial approximation of a Gaussian, blurring each level of the bloom
+const BINOMIAL: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+
+/// A stage of the post-processing of a rendered image, see `Scene::post`. The lens effects
+/// work on the linear colors before they are clipped for display, so the brighter a highlight
+/// the more it blooms. Film grain and sharpening work on the display colors, like they would
+/// on a print, which clips them, so they belong at the end of the chain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PostEffect {
+    /// Light scattered around bright areas. What is brighter than `threshold` is blurred at
+    /// `levels` scales, each twice as wide as the last, and added back scaled by `intensity`.
+    Bloom {
+        threshold: f32,
+        intensity: f32,
+        levels: u32,
+    },
+    /// A star of `points` streaks around bright areas, like the diffraction on the blades of
+    /// an aperture. The streaks fade out over `length` pixels, the first one pointing `angle`
+    /// radians counterclockwise from the right.
+    Glare {
+        threshold: f32,
+        intensity: f32,
+        points: u32,
+        length: f32,
+        angle: f64,
+    },
+    /// Darkening towards the corners by the cosine fourth law. `strength` is the tangent of
+    /// the angle between the corners and the center as seen from the lens.
+    Vignette { strength: f32 },
+    /// Lateral, red spread out from the center and blue drawn in by `strength` as a fraction,
+    /// between -1 and 1.
+    ChromaticAberration { strength: f32 },
+    /// Monochrome noise, strongest in the mid tones. Follows from the scene seed.
+    FilmGrain { amount: f32 },
+    /// An unsharp mask, adding `amount` of the difference to a blur of `radius` pixels.
+    Sharpen { amount: f32, radius: f32 },
+}
+
+impl PostEffect {
+    /// The colors of the `width` by `height` image with the effect applied.
+    pub fn apply(&self, width: u32, height: u32, colors: &[Color], seed: u64) -> Vec<Color> {
+        let (width, height) = (width as usize, height as usize);
+        match *self {
+            PostEffect::Bloom {
+                threshold,
+                intensity,
+                levels,
+            } => bloom(width, height, colors, threshold, intensity, levels),
+            PostEffect::Glare {
+                threshold,
+                intensity,
+                points,
+                length,
+                angle,
+            } => glare(
+                width, height, colors, threshold, intensity, points, length, angle,
+            ),
+            PostEffect::Vignette { strength } => vignette(width, height, colors, strength),
+            PostEffect::ChromaticAberration { strength } => {
+                chromatic_aberration(width, height, colors, strength)
+            }
+            PostEffect::FilmGrain { amount } => film_grain(width, colors, amount, seed),
+            PostEffect::Sharpen { amount, radius } => {
+                let kernel = gaussian_kernel(radius);
+                let encoded: Vec<Color> = colors.iter().map(encode).collect();
+                let blurred = convolve(width, height, &encoded, &kernel);
+                encoded
+                    .iter()
+                    .zip(&blurred)
+                    .map(|(&c, &b)| decode(&(c + (c + b * -1.0) * amount)))
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Apply the `effects` in order.
+pub fn post_process(
+    effects: &[PostEffect],
+    width: u32,
+    height: u32,
+    colors: Vec<Color>,
+    seed: u64,
+) -> Vec<Color> {
+    effects.iter().fold(colors, |colors, effect| {
+        effect.apply(width, height, &colors, seed)
+    })
+}
+
+fn bloom(
+    width: usize,
+    height: usize,
+    colors: &[Color],
+    threshold: f32,
+    intensity: f32,
+    levels: u32,
+) -> Vec<Color> {
+    let mut glow = vec![BLACK; colors.len()];
+    let (mut level_width, mut level_height) = (width, height);
+    let mut level = bright_pass(colors, threshold);
+    for k in 1..=levels {
+        if level_width == 1 && level_height == 1 {
+            break;
+        }
+        let (downsampled, w, h) = downsample(level_width, level_height, &level);
+        level = convolve(w, h, &downsampled, &BINOMIAL);
+        level_width = w;
+        level_height = h;
+
+        let scale = (1u64 << k) as f64;
+        glow.par_iter_mut().enumerate().for_each(|(i, glow)| {
+            let (x, y) = ((i % width) as f64, (i / width) as f64);
+            let (u, v) = ((x + 0.5) / scale - 0.5, (y + 0.5) / scale - 0.5);
+            *glow = *glow + bilinear(level_width, level_height, &level, u, v);
+        });
+    }
+    let weight = intensity / levels.max(1) as f32;
+    colors
+        .iter()
+        .zip(&glow)
+        .map(|(&c, &g)| c + g * weight)
+        .collect()
+}
+
+/// Each streak is an exponential falloff along a line, built up in passes of four taps, each
+/// pass spreading its taps four times as far as the last, so it takes a few passes for any
+/// length.
+#[allow(clippy::too_many_arguments)]
+fn glare(
+    width: usize,
+    height: usize,
+    colors: &[Color],
+    threshold: f32,
+    intensity: f32,
+    points: u32,
+    length: f32,
+    angle: f64,
+) -> Vec<Color> {
+    let bright = bright_pass(colors, threshold);
+    // faded to 5% at the end
+    let falloff = (-3.0 / length.max(1.0) as f64).exp();
+    let mut passes = 0;
+    while 4f32.powi(passes) < length {
+        passes += 1;
+    }
+    let span = 4f64.powi(passes);
+    let total = ((1.0 - falloff.powf(span)) / (1.0 - falloff)) as f32;
+
+    let mut glow = vec![BLACK; colors.len()];
+    for point in 0..points {
+        let direction = angle + 2.0 * PI * point as f64 / points as f64;
+        let (dx, dy) = (direction.cos(), -direction.sin());
+        let mut streak = bright.clone();
+        for pass in 0..passes {
+            let step = 4f64.powi(pass);
+            streak = (0..streak.len())
+                .into_par_iter()
+                .map(|i| {
+                    let (x, y) = ((i % width) as f64, (i / width) as f64);
+                    (0..4).fold(BLACK, |sum, tap| {
+                        let distance = tap as f64 * step;
+                        let (u, v) = (x - dx * distance, y - dy * distance);
+                        if u < 0.0 || v < 0.0 || u > (width - 1) as f64 || v > (height - 1) as f64 {
+                            return sum;
+                        }
+                        let weight = falloff.powf(distance) as f32;
+                        sum + bilinear(width, height, &streak, u, v) * weight
+                    })
+                })
+                .collect();
+        }
+        for (glow, &s) in glow.iter_mut().zip(&streak) {
+            *glow = *glow + s;
+        }
+    }
+    let weight = intensity / (total * points.max(1) as f32);
+    colors
+        .iter()
+        .zip(&glow)
+        .map(|(&c, &g)| c + g * weight)
+        .collect()
+}
+
+fn vignette(width: usize, height: usize, colors: &[Color], strength: f32) -> Vec<Color> {
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    let half_diagonal = (cx * cx + cy * cy).sqrt();
+    colors
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| {
+            let (x, y) = ((i % width) as f32 + 0.5 - cx, (i / width) as f32 + 0.5 - cy);
+            let tangent = (x * x + y * y).sqrt() / half_diagonal * strength;
+            // cos^4 of the angle, from its tangent
+            let cos_squared = 1.0 / (1.0 + tangent * tangent);
+            c * (cos_squared * cos_squared)
+        })
+        .collect()
+}
+
+fn chromatic_aberration(
+    width: usize,
+    height: usize,
+    colors: &[Color],
+    strength: f32,
+) -> Vec<Color> {
+    let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+    // at 1 a channel would be sampled from infinitely far, and past it mirrored
+    let strength = (strength as f64).clamp(-0.999, 0.999);
+    (0..colors.len())
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = ((i % width) as f64 + 0.5 - cx, (i / width) as f64 + 0.5 - cy);
+            let at = |scale: f64| {
+                bilinear(
+                    width,
+                    height,
+                    colors,
+                    cx + x * scale - 0.5,
+                    cy + y * scale - 0.5,
+                )
+            };
+            // a channel magnified on the sensor is sampled closer to the center
+            Color {
+                red: at(1.0 / (1.0 + strength)).red,
+                green: colors[i].green,
+                blue: at(1.0 / (1.0 - strength)).blue,
+            }
+        })
+        .collect()
+}
+
+fn film_grain(width: usize, colors: &[Color], amount: f32, seed: u64) -> Vec<Color> {
+    colors
+        .iter()
+        .enumerate()
+        .map(|(i, color)| {
+            let (x, y) = ((i % width) as u64, (i / width) as u64);
+            let u = sampler::to_unit(sampler::hash(&[seed, x, y, 0]));
+            let v = sampler::to_unit(sampler::hash(&[seed, x, y, 1]));
+            // triangular in -1..1
+            let noise = (u + v - 1.0) as f32;
+            let grain = |c: f32| c + amount * noise * 4.0 * c * (1.0 - c);
+            let encoded = encode(color);
+            decode(&Color {
+                red: grain(encoded.red),
+                green: grain(encoded.green),
+                blue: grain(encoded.blue),
+            })
+        })
+        .collect()
+}
+
+/// The part of the colors brighter than `threshold`, keeping their hue.
+fn bright_pass(colors: &[Color], threshold: f32) -> Vec<Color> {
+    colors
+        .iter()
+        .map(|&c| {
+            let luminance = c.luminance();
+            if luminance > threshold {
+                c * ((luminance - threshold) / luminance)
+            } else {
+                BLACK
+            }
+        })
+        .collect()
+}
+
+/// Half the size, averaging 2x2 blocks.
+fn downsample(width: usize, height: usize, colors: &[Color]) -> (Vec<Color>, usize, usize) {
+    let (w, h) = (width.div_ceil(2), height.div_ceil(2));
+    let at = |x: usize, y: usize| colors[y.min(height - 1) * width + x.min(width - 1)];
+    let downsampled = (0..w * h)
+        .map(|i| {
+            let (x, y) = (2 * (i % w), 2 * (i / w));
+            (at(x, y) + at(x + 1, y) + at(x, y + 1) + at(x + 1, y + 1)) * 0.25
+        })
+        .collect();
+    (downsampled, w, h)
+}
+
+/// The color at a position between pixel centers, clamped to the edges.
+fn bilinear(width: usize, height: usize, colors: &[Color], x: f64, y: f64) -> Color {
+    let x = x.clamp(0.0, (width - 1) as f64);
+    let y = y.clamp(0.0, (height - 1) as f64);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = ((x - x0 as f64) as f32, (y - y0 as f64) as f32);
+    let row = |y: usize| colors[y * width + x0] * (1.0 - fx) + colors[y * width + x1] * fx;
+    row(y0) * (1.0 - fy) + row(y1) * fy
+}
+
+/// Normalized, reaching three standard deviations of `sigma`. With 0 it doesn't blur.
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    if sigma.is_nan() || sigma <= 0.0 {
+        return vec![1.0];
+    }
+    let reach = (3.0 * sigma).ceil().max(1.0) as i32;
+    let weights: Vec<f32> = (-reach..=reach)
+        .map(|x| (-(x * x) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    weights.iter().map(|w| w / total).collect()
+}
+
+/// Blur with the separable `kernel` along x and then y, clamped to the edges.
+fn convolve(width: usize, height: usize, colors: &[Color], kernel: &[f32]) -> Vec<Color> {
+    let reach = (kernel.len() / 2) as i64;
+    let pass = |colors: &[Color], (dx, dy): (i64, i64)| -> Vec<Color> {
+        (0..colors.len())
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = ((i % width) as i64, (i / width) as i64);
+                kernel.iter().enumerate().fold(BLACK, |sum, (k, &weight)| {
+                    let offset = k as i64 - reach;
+                    let qx = (x + offset * dx).clamp(0, width as i64 - 1);
+                    let qy = (y + offset * dy).clamp(0, height as i64 - 1);
+                    sum + colors[(qy * width as i64 + qx) as usize] * weight
+                })
+            })
+            .collect()
+    };
+    pass(&pass(colors, (1, 0)), (0, 1))
+}
+
+/// Clipped and gamma encoded, for the effects working on the display colors.
+fn encode(color: &Color) -> Color {
+    let c = color.clamp();
+    Color {
+        red: gamma_encode(c.red),
+        green: gamma_encode(c.green),
+        blue: gamma_encode(c.blue),
+    }
+}
+
+fn decode(color: &Color) -> Color {
+    let c = color.clamp();
+    Color {
+        red: gamma_decode(c.red),
+        green: gamma_decode(c.green),
+        blue: gamma_decode(c.blue),
+    }
+}
@@ -1,47 +1,287 @@
 use crate::color::{Color, Colorization};
+use crate::csg::Csg;
+use crate::heightfield::Heightfield;
+use crate::medium::Medium;
+use crate::motion::Moving;
 use crate::point::Point;
 use crate::ray::Ray;
+use crate::sdf::DistanceField;
+use crate::texture::{Footprint, ImageTexture, Scalar, TextureMapping};
+use crate::transform::Transform;
+use crate::utils::polynomial_roots;
 use crate::vector3::Vector3;
+use std::sync::Arc;
 
+#[derive(Debug)]
 pub enum SurfaceType {
     Diffuse,
-    Reflective { reflectivity: f32 },
-    Refractive { index: f32, transparency: f32 },
+    Reflective {
+        reflectivity: f32,
+        roughness: Scalar,
+    },
+    Refractive {
+        index: f32,
+        transparency: f32,
+    },
+    /// Invisible, only bounds the medium of the material.
+    Boundary,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct TextureCoordinates {
     pub x: f32,
     pub y: f32,
 }
 
+// Step used to take the finite differences of bump maps
+const BUMP_DELTA: f64 = 1e-3;
+
+#[derive(Debug)]
+pub enum NormalMap {
+    /// A tangent-space normal map, with blue along the geometric normal.
+    Tangent {
+        texture: ImageTexture,
+        strength: f32,
+    },
+    /// A grayscale height map.
+    Bump { height: Scalar, strength: f32 },
+}
+
+#[derive(Debug)]
 pub struct Material {
     pub color: Colorization,
     pub albedo: f32,
     pub surface: SurfaceType,
+    pub normal_map: Option<NormalMap>,
+    pub mapping: TextureMapping,
+    /// The medium filling the inside of the element. Only solids can hold one.
+    pub medium: Option<Medium>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            color: Colorization::Color(Color {
+                red: 0.5,
+                green: 0.5,
+                blue: 0.5,
+            }),
+            albedo: 1.0,
+            surface: SurfaceType::Diffuse,
+            normal_map: None,
+            mapping: TextureMapping::default(),
+            medium: None,
+        }
+    }
 }
 
 pub trait Hittable {
     fn hit(&self, ray: &Ray) -> Option<f64>;
 
-    fn surface_normal(&self, hit_point: &Point) -> Vector3;
+    fn surface_normal(&self, hit_point: &Point, time: f64) -> Vector3;
+
+    fn texture_coordinates(&self, hit_point: &Point, time: f64) -> TextureCoordinates;
+
+    /// The direction along which the x texture coordinate grows.
+    fn tangent(&self, hit_point: &Point, time: f64) -> Vector3;
+
+    /// The sorted spans of the ray that are inside the solid, including the ones behind
+    /// the ray origin. Surfaces without an inside, like disks, have none, so `Scene::validate`
+    /// doesn't let them be CSG operands or hold a medium.
+    fn intervals(&self, _ray: &Ray) -> Vec<Interval> {
+        Vec::new()
+    }
+}
 
-    fn texture_coordinates(&self, hit_point: &Point) -> TextureCoordinates;
+/// A span of a ray between entering and leaving a solid.
+#[derive(Clone, Copy, Debug)]
+pub struct Interval {
+    pub enter: f64,
+    pub exit: f64,
+}
+
+/// The single interval of a convex solid, given every distance where the ray crosses its boundary.
+fn convex_interval(crossings: &[Option<f64>]) -> Vec<Interval> {
+    let mut enter = f64::INFINITY;
+    let mut exit = f64::NEG_INFINITY;
+    for &t in crossings.iter().flatten() {
+        enter = enter.min(t);
+        exit = exit.max(t);
+    }
+    if enter <= exit {
+        vec![Interval { enter, exit }]
+    } else {
+        Vec::new()
+    }
 }
 
+// Elements are only stored once in the scene, so their size doesn't matter much
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
 pub enum Element {
     Sphere(Sphere),
     Plane(Plane),
+    Box(AxisAlignedBox),
+    Disk(Disk),
+    Rectangle(Rectangle),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Torus(Torus),
+    Instance(Instance),
+    Csg(Csg),
+    Sdf(DistanceField),
+    Heightfield(Heightfield),
+    Moving(Moving),
 }
 
 impl Element {
-    pub fn color(&self, hit_point: &Point) -> Color {
-        match *self {
-            Element::Sphere(ref s) => s.material.color.color(&s.texture_coordinates(&hit_point)),
-            Element::Plane(ref p) => {
-                let text_coords = &p.texture_coordinates(&hit_point);
-                p.material.color.color(text_coords)
+    pub fn color(&self, hit_point: &Point, time: f64, footprint: &Footprint) -> Color {
+        let material = self.material();
+        material.mapping.blend(
+            &self.texture_coordinates(hit_point, time),
+            hit_point,
+            &self.surface_normal(hit_point, time),
+            |coords| material.color.sample(coords, hit_point, footprint),
+        )
+    }
+
+    /// Evaluate a material parameter at the hit point.
+    pub fn scalar(&self, scalar: &Scalar, hit_point: &Point, time: f64) -> f32 {
+        self.material().mapping.blend(
+            &self.texture_coordinates(hit_point, time),
+            hit_point,
+            &self.surface_normal(hit_point, time),
+            |coords| scalar.value(coords, hit_point),
+        )
+    }
+
+    /// The texture coordinates after applying the mapping of the material.
+    pub fn mapped_coordinates(&self, hit_point: &Point, time: f64) -> TextureCoordinates {
+        self.material().mapping.coordinates(
+            &self.texture_coordinates(hit_point, time),
+            hit_point,
+            &self.surface_normal(hit_point, time),
+        )
+    }
+
+    /// Like `tangent`, but following the mapped texture coordinates.
+    fn mapped_tangent(&self, hit_point: &Point, time: f64, normal: &Vector3) -> Vector3 {
+        if self.material().mapping.is_identity() {
+            return self.tangent(hit_point, time);
+        }
+        let (first, second) = normal.orthonormal_basis();
+        let du = |axis: Vector3| {
+            let forward = self.mapped_coordinates(&(*hit_point + axis * BUMP_DELTA), time);
+            let backward = self.mapped_coordinates(&(*hit_point + axis * -BUMP_DELTA), time);
+            ((forward.x - backward.x) as f64) / (2.0 * BUMP_DELTA)
+        };
+        let tangent = first * du(first) + second * du(second);
+        if tangent.length() < 1e-9 || !tangent.length().is_finite() {
+            self.tangent(hit_point, time)
+        } else {
+            tangent.normalize()
+        }
+    }
+
+    /// The surface normal perturbed by the normal or bump map of the material, if any.
+    pub fn shading_normal(&self, hit_point: &Point, time: f64) -> Vector3 {
+        let geometric = self.surface_normal(hit_point, time);
+        let normal_map = match self.material().normal_map {
+            Some(ref map) => map,
+            None => return geometric,
+        };
+
+        let normal = geometric.normalize();
+        let tangent = self.mapped_tangent(hit_point, time, &normal);
+        // Gram-Schmidt, so the frame stays orthonormal even for approximate tangents
+        let tangent = (tangent - normal * normal.dot(&tangent)).normalize();
+        // The texture v axis may run either way around the normal (a plane faces against its
+        // own normal), so turn the bitangent along it
+        let bitangent = normal.cross(&tangent);
+        let forward = self.mapped_coordinates(&(*hit_point + bitangent * BUMP_DELTA), time);
+        let backward = self.mapped_coordinates(&(*hit_point + bitangent * -BUMP_DELTA), time);
+        let bitangent = if forward.y < backward.y {
+            -bitangent
+        } else {
+            bitangent
+        };
+
+        let perturbed = match *normal_map {
+            NormalMap::Tangent {
+                ref texture,
+                strength,
+            } => {
+                let texel = texture.sample(
+                    &self.mapped_coordinates(hit_point, time),
+                    &Footprint::point(),
+                );
+                let x = ((texel.red * 2.0 - 1.0) * strength) as f64;
+                let y = ((texel.green * 2.0 - 1.0) * strength) as f64;
+                let z = (texel.blue * 2.0 - 1.0) as f64;
+                tangent * x + bitangent * y + normal * z
+            }
+            NormalMap::Bump {
+                ref height,
+                strength,
+            } => {
+                let height_at = |p: &Point| self.scalar(height, p, time) as f64;
+                let h = height_at(hit_point);
+                let dt = (height_at(&(*hit_point + tangent * BUMP_DELTA)) - h) / BUMP_DELTA;
+                let db = (height_at(&(*hit_point + bitangent * BUMP_DELTA)) - h) / BUMP_DELTA;
+                normal - (tangent * dt + bitangent * db) * strength as f64
             }
+        };
+
+        let perturbed = perturbed.normalize();
+        if perturbed.x.is_finite() && perturbed.y.is_finite() && perturbed.z.is_finite() {
+            perturbed
+        } else {
+            normal
+        }
+    }
+
+    /// Estimate the texture-space footprint of a ray cone of `width` hitting the surface.
+    pub fn footprint(
+        &self,
+        hit_point: &Point,
+        time: f64,
+        normal: Vector3,
+        direction: Vector3,
+        width: f64,
+    ) -> Footprint {
+        let normal = normal.normalize();
+        let cos_theta = direction.dot(&normal).abs();
+        if width <= 0.0 || cos_theta < 1e-6 {
+            return Footprint::point();
+        }
+        let across = direction.cross(&normal);
+        let across = if across.length() < 1e-9 {
+            normal.orthonormal_basis().0
+        } else {
+            across.normalize()
+        };
+        let along = normal.cross(&across).normalize();
+
+        // central differences, kept small so that they rarely straddle a texture seam
+        let derivative = |axis: Vector3, length: f64| {
+            let h = length * 0.01;
+            let forward = self.mapped_coordinates(&(*hit_point + axis * h), time);
+            let backward = self.mapped_coordinates(&(*hit_point + axis * -h), time);
+            let scale = (length / (2.0 * h)) as f32;
+            let axis = TextureCoordinates {
+                x: (forward.x - backward.x) * scale,
+                y: (forward.y - backward.y) * scale,
+            };
+            if axis.x.is_finite() && axis.y.is_finite() {
+                axis
+            } else {
+                TextureCoordinates { x: 0.0, y: 0.0 }
+            }
+        };
+
+        Footprint {
+            dx: derivative(across, width),
+            dy: derivative(along, width / cos_theta),
         }
     }
 
@@ -49,24 +289,58 @@ impl Element {
         match *self {
             Element::Sphere(ref s) => &s.material,
             Element::Plane(ref p) => &p.material,
+            Element::Box(ref b) => &b.material,
+            Element::Disk(ref d) => &d.material,
+            Element::Rectangle(ref r) => &r.material,
+            Element::Cylinder(ref c) => &c.material,
+            Element::Cone(ref c) => &c.material,
+            Element::Torus(ref t) => &t.material,
+            Element::Instance(ref i) => match i.material {
+                Some(ref m) => m,
+                None => i.element.material(),
+            },
+            Element::Csg(ref c) => &c.material,
+            Element::Sdf(ref f) => &f.material,
+            Element::Heightfield(ref h) => &h.material,
+            Element::Moving(ref m) => match m.material {
+                Some(ref material) => material,
+                None => m.element.material(),
+            },
         }
     }
 
-    pub fn material_mut(&mut self) -> &mut Material {
+    /// `None` for an instance or moving element without its own material whose geometry is
+    /// shared.
+    pub fn material_mut(&mut self) -> Option<&mut Material> {
         match *self {
-            Element::Sphere(ref mut s) => &mut s.material,
-            Element::Plane(ref mut p) => &mut p.material,
+            Element::Sphere(ref mut s) => Some(&mut s.material),
+            Element::Plane(ref mut p) => Some(&mut p.material),
+            Element::Box(ref mut b) => Some(&mut b.material),
+            Element::Disk(ref mut d) => Some(&mut d.material),
+            Element::Rectangle(ref mut r) => Some(&mut r.material),
+            Element::Cylinder(ref mut c) => Some(&mut c.material),
+            Element::Cone(ref mut c) => Some(&mut c.material),
+            Element::Torus(ref mut t) => Some(&mut t.material),
+            Element::Instance(ref mut i) => match i.material {
+                Some(ref mut m) => Some(m),
+                None => Arc::get_mut(&mut i.element)?.material_mut(),
+            },
+            Element::Csg(ref mut c) => Some(&mut c.material),
+            Element::Sdf(ref mut f) => Some(&mut f.material),
+            Element::Heightfield(ref mut h) => Some(&mut h.material),
+            Element::Moving(ref mut m) => match m.material {
+                Some(ref mut material) => Some(material),
+                None => Arc::get_mut(&mut m.element)?.material_mut(),
+            },
         }
     }
 
     pub fn albedo(&self) -> f32 {
-        match *self {
-            Element::Sphere(ref s) => s.material.albedo,
-            Element::Plane(ref p) => p.material.albedo,
-        }
+        self.material().albedo
     }
 }
 
+#[derive(Debug)]
 pub struct Sphere {
     pub center: Point,
     pub radius: f64,
@@ -85,31 +359,110 @@ impl Plane {
     }
 }
 
+#[derive(Debug)]
 pub struct Plane {
     pub p: Point,
     pub normal: Vector3,
     pub material: Material,
 }
 
+/// Shared geometry placed in the scene with a transform.
+#[derive(Debug)]
+pub struct Instance {
+    pub element: Arc<Element>,
+    pub transform: Transform,
+    /// Overrides the material of the geometry when set.
+    pub material: Option<Material>,
+}
+
 impl Hittable for Element {
     fn hit(&self, ray: &Ray) -> Option<f64> {
         match *self {
             Element::Sphere(ref s) => s.hit(ray),
             Element::Plane(ref p) => p.hit(ray),
+            Element::Box(ref b) => b.hit(ray),
+            Element::Disk(ref d) => d.hit(ray),
+            Element::Rectangle(ref r) => r.hit(ray),
+            Element::Cylinder(ref c) => c.hit(ray),
+            Element::Cone(ref c) => c.hit(ray),
+            Element::Torus(ref t) => t.hit(ray),
+            Element::Instance(ref i) => i.hit(ray),
+            Element::Csg(ref c) => c.hit(ray),
+            Element::Sdf(ref f) => f.hit(ray),
+            Element::Heightfield(ref h) => h.hit(ray),
+            Element::Moving(ref m) => m.hit(ray),
         }
     }
 
-    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+    fn surface_normal(&self, hit_point: &Point, time: f64) -> Vector3 {
         match *self {
             Element::Sphere(ref s) => s.surface_normal(hit_point),
             Element::Plane(ref p) => p.surface_normal(hit_point),
+            Element::Box(ref b) => b.surface_normal(hit_point, time),
+            Element::Disk(ref d) => d.surface_normal(hit_point, time),
+            Element::Rectangle(ref r) => r.surface_normal(hit_point, time),
+            Element::Cylinder(ref c) => c.surface_normal(hit_point, time),
+            Element::Cone(ref c) => c.surface_normal(hit_point, time),
+            Element::Torus(ref t) => t.surface_normal(hit_point, time),
+            Element::Instance(ref i) => i.surface_normal(hit_point, time),
+            Element::Csg(ref c) => c.surface_normal(hit_point, time),
+            Element::Sdf(ref f) => f.surface_normal(hit_point, time),
+            Element::Heightfield(ref h) => h.surface_normal(hit_point, time),
+            Element::Moving(ref m) => m.surface_normal(hit_point, time),
         }
     }
 
-    fn texture_coordinates(&self, hit_point: &Point) -> TextureCoordinates {
+    fn texture_coordinates(&self, hit_point: &Point, time: f64) -> TextureCoordinates {
         match *self {
-            Element::Sphere(ref s) => s.texture_coordinates(hit_point),
-            Element::Plane(ref p) => p.texture_coordinates(hit_point),
+            Element::Sphere(ref s) => s.texture_coordinates(hit_point, time),
+            Element::Plane(ref p) => p.texture_coordinates(hit_point, time),
+            Element::Box(ref b) => b.texture_coordinates(hit_point, time),
+            Element::Disk(ref d) => d.texture_coordinates(hit_point, time),
+            Element::Rectangle(ref r) => r.texture_coordinates(hit_point, time),
+            Element::Cylinder(ref c) => c.texture_coordinates(hit_point, time),
+            Element::Cone(ref c) => c.texture_coordinates(hit_point, time),
+            Element::Torus(ref t) => t.texture_coordinates(hit_point, time),
+            Element::Instance(ref i) => i.texture_coordinates(hit_point, time),
+            Element::Csg(ref c) => c.texture_coordinates(hit_point, time),
+            Element::Sdf(ref f) => f.texture_coordinates(hit_point, time),
+            Element::Heightfield(ref h) => h.texture_coordinates(hit_point, time),
+            Element::Moving(ref m) => m.texture_coordinates(hit_point, time),
+        }
+    }
+
+    fn tangent(&self, hit_point: &Point, time: f64) -> Vector3 {
+        match *self {
+            Element::Sphere(ref s) => s.tangent(hit_point, time),
+            Element::Plane(ref p) => p.tangent(hit_point, time),
+            Element::Box(ref b) => b.tangent(hit_point, time),
+            Element::Disk(ref d) => d.tangent(hit_point, time),
+            Element::Rectangle(ref r) => r.tangent(hit_point, time),
+            Element::Cylinder(ref c) => c.tangent(hit_point, time),
+            Element::Cone(ref c) => c.tangent(hit_point, time),
+            Element::Torus(ref t) => t.tangent(hit_point, time),
+            Element::Instance(ref i) => i.tangent(hit_point, time),
+            Element::Csg(ref c) => c.tangent(hit_point, time),
+            Element::Sdf(ref f) => f.tangent(hit_point, time),
+            Element::Heightfield(ref h) => h.tangent(hit_point, time),
+            Element::Moving(ref m) => m.tangent(hit_point, time),
+        }
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        match *self {
+            Element::Sphere(ref s) => s.intervals(ray),
+            Element::Plane(ref p) => p.intervals(ray),
+            Element::Box(ref b) => b.intervals(ray),
+            Element::Disk(ref d) => d.intervals(ray),
+            Element::Rectangle(ref r) => r.intervals(ray),
+            Element::Cylinder(ref c) => c.intervals(ray),
+            Element::Cone(ref c) => c.intervals(ray),
+            Element::Torus(ref t) => t.intervals(ray),
+            Element::Instance(ref i) => i.intervals(ray),
+            Element::Csg(ref c) => c.intervals(ray),
+            Element::Sdf(ref f) => f.intervals(ray),
+            Element::Heightfield(ref h) => h.intervals(ray),
+            Element::Moving(ref m) => m.intervals(ray),
         }
     }
 }
@@ -136,7 +489,7 @@ impl Hittable for Sphere {
         let t1 = hypo + thc;
 
         if t0 < 0.0 && t1 < 0.0 {
-            return None;
+            None
         } else if t0 < 0.0 {
             Some(t1)
         } else if t1 < 0.0 {
@@ -148,17 +501,51 @@ impl Hittable for Sphere {
         }
     }
 
-    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+    fn surface_normal(&self, hit_point: &Point, _: f64) -> Vector3 {
         (*hit_point - self.center).normalize()
     }
 
-    fn texture_coordinates(&self, hit_point: &Point) -> TextureCoordinates {
+    fn texture_coordinates(&self, hit_point: &Point, _: f64) -> TextureCoordinates {
         let hit_vector = *hit_point - self.center;
         TextureCoordinates {
             x: (1.0 + (hit_vector.z.atan2(hit_vector.x) as f32) / std::f32::consts::PI) * 0.5,
             y: (hit_vector.y / self.radius).acos() as f32 / std::f32::consts::PI,
         }
     }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let l = self.center - ray.origin;
+        let hypo = l.dot(&ray.direction);
+        let dist = l.dot(&l) - (hypo * hypo);
+        let radius_squared = self.radius * self.radius;
+        if dist > radius_squared {
+            return Vec::new();
+        }
+        let thc = (radius_squared - dist).sqrt();
+        vec![Interval {
+            enter: hypo - thc,
+            exit: hypo + thc,
+        }]
+    }
+
+    fn tangent(&self, hit_point: &Point, _: f64) -> Vector3 {
+        let hit_vector = *hit_point - self.center;
+        let tangent = Vector3 {
+            x: -hit_vector.z,
+            y: 0.0,
+            z: hit_vector.x,
+        };
+        // the poles have no preferred direction
+        if tangent.length() < 1e-9 {
+            Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            }
+        } else {
+            tangent.normalize()
+        }
+    }
 }
 
 impl Hittable for Plane {
@@ -168,7 +555,7 @@ impl Hittable for Plane {
         // numerical errors
         if denominator > 1e-6 {
             let v = self.p - ray.origin;
-            let dist = v.dot(&normal) / denominator;
+            let dist = v.dot(normal) / denominator;
             if dist >= 0.0 {
                 return Some(dist);
             }
@@ -176,24 +563,12 @@ impl Hittable for Plane {
         None
     }
 
-    fn surface_normal(&self, _: &Point) -> Vector3 {
+    fn surface_normal(&self, _: &Point, _: f64) -> Vector3 {
         -self.normal
     }
 
-    fn texture_coordinates(&self, hit_point: &Point) -> TextureCoordinates {
-        let mut x_axis = self.normal.cross(&Vector3 {
-            x: 0.0,
-            y: 0.0,
-            z: 1.0,
-        });
-        if x_axis.length() == 0.0 {
-            x_axis = self.normal.cross(&Vector3 {
-                x: 0.0,
-                y: 1.0,
-                z: 0.0,
-            });
-        }
-        let y_axis = self.normal.cross(&x_axis);
+    fn texture_coordinates(&self, hit_point: &Point, _: f64) -> TextureCoordinates {
+        let (x_axis, y_axis) = plane_axes(&self.normal);
         let hit_vector = *hit_point - self.p;
 
         TextureCoordinates {
@@ -201,6 +576,93 @@ impl Hittable for Plane {
             y: hit_vector.dot(&y_axis) as f32,
         }
     }
+
+    fn tangent(&self, _: &Point, _: f64) -> Vector3 {
+        plane_axes(&self.normal).0
+    }
+
+    /// The half-space behind the plane, on the side `normal` points to.
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let denominator = self.normal.dot(&ray.direction);
+        let offset = (self.p - ray.origin).dot(&self.normal);
+        if denominator.abs() < 1e-12 {
+            return if offset < 0.0 {
+                vec![Interval {
+                    enter: f64::NEG_INFINITY,
+                    exit: f64::INFINITY,
+                }]
+            } else {
+                Vec::new()
+            };
+        }
+        let dist = offset / denominator;
+        if denominator > 0.0 {
+            vec![Interval {
+                enter: dist,
+                exit: f64::INFINITY,
+            }]
+        } else {
+            vec![Interval {
+                enter: f64::NEG_INFINITY,
+                exit: dist,
+            }]
+        }
+    }
+}
+
+impl Instance {
+    /// The ray in object space, and the factor converting world distances to object space ones.
+    fn local_ray(&self, ray: &Ray) -> (Ray, f64) {
+        let direction = self.transform.vector_to_local(&ray.direction);
+        // the object space direction isn't unit length under scaling,
+        // so distances have to be converted back
+        let scale = direction.length();
+        let local_ray = Ray {
+            origin: self.transform.point_to_local(&ray.origin),
+            direction: direction / scale,
+            cone: ray.cone,
+            time: ray.time,
+        };
+        (local_ray, scale)
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray) -> Option<f64> {
+        let (local_ray, scale) = self.local_ray(ray);
+        self.element.hit(&local_ray).map(|d| d / scale)
+    }
+
+    fn surface_normal(&self, hit_point: &Point, time: f64) -> Vector3 {
+        let local_normal = self
+            .element
+            .surface_normal(&self.transform.point_to_local(hit_point), time);
+        self.transform.normal_to_world(&local_normal).normalize()
+    }
+
+    fn texture_coordinates(&self, hit_point: &Point, time: f64) -> TextureCoordinates {
+        self.element
+            .texture_coordinates(&self.transform.point_to_local(hit_point), time)
+    }
+
+    fn tangent(&self, hit_point: &Point, time: f64) -> Vector3 {
+        let local_tangent = self
+            .element
+            .tangent(&self.transform.point_to_local(hit_point), time);
+        self.transform.vector_to_world(&local_tangent).normalize()
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let (local_ray, scale) = self.local_ray(ray);
+        self.element
+            .intervals(&local_ray)
+            .into_iter()
+            .map(|i| Interval {
+                enter: i.enter / scale,
+                exit: i.exit / scale,
+            })
+            .collect()
+    }
 }
 
 pub struct Intersection<'a> {
@@ -209,13 +671,621 @@ pub struct Intersection<'a> {
 }
 
 impl<'a> Intersection<'a> {
-    pub fn new<'b>(distance: f64, element: &'b Element) -> Intersection<'b> {
-        if !distance.is_finite() {
-            panic!("Intersection must have a finite distance.");
+    /// `None` if the distance isn't finite.
+    pub fn new<'b>(distance: f64, element: &'b Element) -> Option<Intersection<'b>> {
+        distance
+            .is_finite()
+            .then_some(Intersection { distance, element })
+    }
+}
+
+/// The texture space axes lying in a plane with the given normal.
+pub(crate) fn plane_axes(normal: &Vector3) -> (Vector3, Vector3) {
+    let mut x_axis = normal.cross(&Vector3 {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+    });
+    if x_axis.length() == 0.0 {
+        x_axis = normal.cross(&Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        });
+    }
+    // normalized, so that the texture scale doesn't depend on the length of the normal
+    let x_axis = x_axis.normalize();
+    let y_axis = normal.cross(&x_axis).normalize();
+    (x_axis, y_axis)
+}
+
+const UP: Vector3 = Vector3 {
+    x: 0.0,
+    y: 1.0,
+    z: 0.0,
+};
+
+// Tolerance used to tell which face of a shape a hit point lies on
+const FACE_EPSILON: f64 = 1e-6;
+
+fn around_y_tangent(local: &Vector3) -> Vector3 {
+    let tangent = Vector3 {
+        x: -local.z,
+        y: 0.0,
+        z: local.x,
+    };
+    if tangent.length() < 1e-9 {
+        Vector3 {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    } else {
+        tangent.normalize()
+    }
+}
+
+fn angle_around_y(local: &Vector3) -> f32 {
+    (1.0 + (local.z.atan2(local.x) as f32) / std::f32::consts::PI) * 0.5
+}
+
+/// Closest non-negative of the candidate distances.
+fn nearest(candidates: &[Option<f64>]) -> Option<f64> {
+    candidates
+        .iter()
+        .flatten()
+        .filter(|t| **t >= 0.0)
+        .fold(None, |closest: Option<f64>, &t| match closest {
+            Some(c) if c <= t => Some(c),
+            _ => Some(t),
+        })
+}
+
+/// Both roots of `a t^2 + b t + c`, in increasing order.
+pub(crate) fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 {
+            return None;
+        }
+        let t = -c / b;
+        return Some((t, t));
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    // the numerically stable form, avoiding cancellation
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let (t0, t1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+/// Distance along the ray to the plane `y = height` in local coordinates.
+fn cap_hit(origin: &Vector3, direction: &Vector3, height: f64, radius: f64) -> Option<f64> {
+    if direction.y.abs() < 1e-12 {
+        return None;
+    }
+    let t = (height - origin.y) / direction.y;
+    let x = origin.x + t * direction.x;
+    let z = origin.z + t * direction.z;
+    if x * x + z * z <= radius * radius {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+/// The entry and exit distances of the ray through the box from `min` to `max`.
+pub(crate) fn slabs(min: &Point, max: &Point, ray: &Ray) -> Option<(f64, f64)> {
+    let mut t_min = f64::NEG_INFINITY;
+    let mut t_max = f64::INFINITY;
+    let axes = [
+        (ray.origin.x, ray.direction.x, min.x, max.x),
+        (ray.origin.y, ray.direction.y, min.y, max.y),
+        (ray.origin.z, ray.direction.z, min.z, max.z),
+    ];
+    for &(origin, direction, min, max) in axes.iter() {
+        if direction.abs() < 1e-12 {
+            if origin < min || origin > max {
+                return None;
+            }
+            continue;
+        }
+        let t0 = (min - origin) / direction;
+        let t1 = (max - origin) / direction;
+        t_min = t_min.max(t0.min(t1));
+        t_max = t_max.min(t0.max(t1));
+    }
+    if t_min > t_max {
+        None
+    } else {
+        Some((t_min, t_max))
+    }
+}
+
+#[derive(Debug)]
+pub struct AxisAlignedBox {
+    pub min: Point,
+    pub max: Point,
+    pub material: Material,
+}
+
+impl AxisAlignedBox {
+    /// The entry and exit distances of the ray through the box, which may be negative.
+    pub fn slabs(&self, ray: &Ray) -> Option<(f64, f64)> {
+        slabs(&self.min, &self.max, ray)
+    }
+
+    /// The axis of the face the point lies on (0, 1 or 2) and whether it's the max side.
+    fn face(&self, hit_point: &Point) -> (usize, bool) {
+        let candidates = [
+            (hit_point.x - self.min.x).abs(),
+            (hit_point.x - self.max.x).abs(),
+            (hit_point.y - self.min.y).abs(),
+            (hit_point.y - self.max.y).abs(),
+            (hit_point.z - self.min.z).abs(),
+            (hit_point.z - self.max.z).abs(),
+        ];
+        let closest = (0..6)
+            .min_by(|&a, &b| candidates[a].total_cmp(&candidates[b]))
+            .unwrap_or(0);
+        (closest / 2, closest % 2 == 1)
+    }
+}
+
+impl Hittable for AxisAlignedBox {
+    fn hit(&self, ray: &Ray) -> Option<f64> {
+        let (t_min, t_max) = self.slabs(ray)?;
+        nearest(&[Some(t_min), Some(t_max)])
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        match self.slabs(ray) {
+            Some((enter, exit)) => vec![Interval { enter, exit }],
+            None => Vec::new(),
         }
-        Intersection {
-            distance: distance,
-            element: element,
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: f64) -> Vector3 {
+        let (axis, max_side) = self.face(hit_point);
+        let sign = if max_side { 1.0 } else { -1.0 };
+        match axis {
+            0 => Vector3 {
+                x: sign,
+                y: 0.0,
+                z: 0.0,
+            },
+            1 => Vector3 {
+                x: 0.0,
+                y: sign,
+                z: 0.0,
+            },
+            _ => Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: sign,
+            },
         }
     }
+
+    fn texture_coordinates(&self, hit_point: &Point, _: f64) -> TextureCoordinates {
+        let size = self.max - self.min;
+        let relative = *hit_point - self.min;
+        let (u, v) = match self.face(hit_point).0 {
+            0 => (relative.z / size.z, relative.y / size.y),
+            1 => (relative.x / size.x, relative.z / size.z),
+            _ => (relative.x / size.x, relative.y / size.y),
+        };
+        TextureCoordinates {
+            x: u as f32,
+            y: v as f32,
+        }
+    }
+
+    fn tangent(&self, hit_point: &Point, _: f64) -> Vector3 {
+        match self.face(hit_point).0 {
+            0 => Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            },
+            _ => Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+        }
+    }
+}
+
+/// A flat disk. Like for `Plane`, `normal` points away from the visible side.
+#[derive(Debug)]
+pub struct Disk {
+    pub center: Point,
+    pub normal: Vector3,
+    pub radius: f64,
+    pub material: Material,
+}
+
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray) -> Option<f64> {
+        let denominator = self.normal.dot(&ray.direction);
+        if denominator > 1e-6 {
+            let dist = (self.center - ray.origin).dot(&self.normal) / denominator;
+            if dist >= 0.0
+                && (ray.at(dist) - self.center).length_squared() <= self.radius * self.radius
+            {
+                return Some(dist);
+            }
+        }
+        None
+    }
+
+    fn surface_normal(&self, _: &Point, _: f64) -> Vector3 {
+        -self.normal.normalize()
+    }
+
+    fn texture_coordinates(&self, hit_point: &Point, _: f64) -> TextureCoordinates {
+        let (x_axis, y_axis) = plane_axes(&self.normal);
+        let hit_vector = *hit_point - self.center;
+        let (x, y) = (hit_vector.dot(&x_axis), hit_vector.dot(&y_axis));
+        TextureCoordinates {
+            x: (1.0 + (y.atan2(x) as f32) / std::f32::consts::PI) * 0.5,
+            y: (hit_vector.length() / self.radius) as f32,
+        }
+    }
+
+    fn tangent(&self, hit_point: &Point, _: f64) -> Vector3 {
+        let hit_vector = *hit_point - self.center;
+        let tangent = self.normal.cross(&hit_vector);
+        if tangent.length() < 1e-9 {
+            plane_axes(&self.normal).0
+        } else {
+            tangent.normalize()
+        }
+    }
+}
+
+/// A parallelogram spanned by the edges `u` and `v` from `corner`.
+/// Like for `Plane`, `u x v` points away from the visible side.
+#[derive(Debug)]
+pub struct Rectangle {
+    pub corner: Point,
+    pub u: Vector3,
+    pub v: Vector3,
+    pub material: Material,
+}
+
+impl Hittable for Rectangle {
+    fn hit(&self, ray: &Ray) -> Option<f64> {
+        let normal = self.u.cross(&self.v);
+        let denominator = normal.dot(&ray.direction);
+        if denominator > 1e-6 {
+            let dist = (self.corner - ray.origin).dot(&normal) / denominator;
+            if dist >= 0.0 {
+                let coords = self.texture_coordinates(&ray.at(dist), ray.time);
+                if (0.0..=1.0).contains(&coords.x) && (0.0..=1.0).contains(&coords.y) {
+                    return Some(dist);
+                }
+            }
+        }
+        None
+    }
+
+    fn surface_normal(&self, _: &Point, _: f64) -> Vector3 {
+        self.v.cross(&self.u).normalize()
+    }
+
+    fn texture_coordinates(&self, hit_point: &Point, _: f64) -> TextureCoordinates {
+        let hit_vector = *hit_point - self.corner;
+        // solve hit_vector = a * u + b * v, which also works for skewed edges
+        let uu = self.u.dot(&self.u);
+        let uv = self.u.dot(&self.v);
+        let vv = self.v.dot(&self.v);
+        let wu = hit_vector.dot(&self.u);
+        let wv = hit_vector.dot(&self.v);
+        let determinant = uu * vv - uv * uv;
+        TextureCoordinates {
+            x: ((wu * vv - wv * uv) / determinant) as f32,
+            y: ((wv * uu - wu * uv) / determinant) as f32,
+        }
+    }
+
+    fn tangent(&self, _: &Point, _: f64) -> Vector3 {
+        self.u.normalize()
+    }
+}
+
+/// A capped cylinder standing on `base`, along the y axis.
+#[derive(Debug)]
+pub struct Cylinder {
+    pub base: Point,
+    pub radius: f64,
+    pub height: f64,
+    pub material: Material,
+}
+
+impl Cylinder {
+    /// Distances where the ray crosses the side of the infinite cylinder.
+    fn side_hits(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let o = ray.origin - self.base;
+        let d = ray.direction;
+        solve_quadratic(
+            d.x * d.x + d.z * d.z,
+            2.0 * (o.x * d.x + o.z * d.z),
+            o.x * o.x + o.z * o.z - self.radius * self.radius,
+        )
+    }
+
+    fn on_side(&self, ray: &Ray, t: f64) -> Option<f64> {
+        let y = ray.at(t).y - self.base.y;
+        if (0.0..=self.height).contains(&y) {
+            Some(t)
+        } else {
+            None
+        }
+    }
+
+    /// Every distance where the ray crosses the side or the caps.
+    fn crossings(&self, ray: &Ray) -> [Option<f64>; 4] {
+        let o = ray.origin - self.base;
+        let (side0, side1) = match self.side_hits(ray) {
+            Some((t0, t1)) => (self.on_side(ray, t0), self.on_side(ray, t1)),
+            None => (None, None),
+        };
+        [
+            side0,
+            side1,
+            cap_hit(&o, &ray.direction, 0.0, self.radius),
+            cap_hit(&o, &ray.direction, self.height, self.radius),
+        ]
+    }
+
+    /// Whether the point is on one of the caps rather than on the side.
+    fn cap(&self, local: &Vector3) -> Option<bool> {
+        let side_distance = ((local.x * local.x + local.z * local.z).sqrt() - self.radius).abs();
+        let bottom = local.y.abs();
+        let top = (local.y - self.height).abs();
+        if side_distance <= bottom.min(top) {
+            None
+        } else {
+            Some(top < bottom)
+        }
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, ray: &Ray) -> Option<f64> {
+        nearest(&self.crossings(ray))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        convex_interval(&self.crossings(ray))
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: f64) -> Vector3 {
+        let local = *hit_point - self.base;
+        match self.cap(&local) {
+            Some(true) => UP,
+            Some(false) => -UP,
+            None => Vector3 {
+                x: local.x,
+                y: 0.0,
+                z: local.z,
+            }
+            .normalize(),
+        }
+    }
+
+    fn texture_coordinates(&self, hit_point: &Point, _: f64) -> TextureCoordinates {
+        let local = *hit_point - self.base;
+        match self.cap(&local) {
+            Some(_) => TextureCoordinates {
+                x: (0.5 + 0.5 * local.x / self.radius) as f32,
+                y: (0.5 + 0.5 * local.z / self.radius) as f32,
+            },
+            None => TextureCoordinates {
+                x: angle_around_y(&local),
+                y: (local.y / self.height) as f32,
+            },
+        }
+    }
+
+    fn tangent(&self, hit_point: &Point, _: f64) -> Vector3 {
+        let local = *hit_point - self.base;
+        match self.cap(&local) {
+            Some(_) => Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            None => around_y_tangent(&local),
+        }
+    }
+}
+
+/// A capped cone standing on `base`, with its apex `height` above it along the y axis.
+#[derive(Debug)]
+pub struct Cone {
+    pub base: Point,
+    pub radius: f64,
+    pub height: f64,
+    pub material: Material,
+}
+
+impl Cone {
+    fn side_hits(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let o = ray.origin - self.base;
+        let d = ray.direction;
+        let k = self.radius / self.height;
+        let k2 = k * k;
+        let h = self.height - o.y;
+        solve_quadratic(
+            d.x * d.x + d.z * d.z - k2 * d.y * d.y,
+            2.0 * (o.x * d.x + o.z * d.z + k2 * h * d.y),
+            o.x * o.x + o.z * o.z - k2 * h * h,
+        )
+    }
+
+    fn on_side(&self, ray: &Ray, t: f64) -> Option<f64> {
+        // the equation also describes the mirrored cone above the apex
+        let y = ray.at(t).y - self.base.y;
+        if (0.0..=self.height).contains(&y) {
+            Some(t)
+        } else {
+            None
+        }
+    }
+
+    /// Every distance where the ray crosses the side or the base.
+    fn crossings(&self, ray: &Ray) -> [Option<f64>; 3] {
+        let o = ray.origin - self.base;
+        let (side0, side1) = match self.side_hits(ray) {
+            Some((t0, t1)) => (self.on_side(ray, t0), self.on_side(ray, t1)),
+            None => (None, None),
+        };
+        [side0, side1, cap_hit(&o, &ray.direction, 0.0, self.radius)]
+    }
+
+    fn is_cap(&self, local: &Vector3) -> bool {
+        let slope = self.radius / self.height;
+        let side_radius = slope * (self.height - local.y);
+        let side_distance = ((local.x * local.x + local.z * local.z).sqrt() - side_radius).abs()
+            / (1.0 + slope * slope).sqrt();
+        local.y.abs() < side_distance
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, ray: &Ray) -> Option<f64> {
+        nearest(&self.crossings(ray))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        convex_interval(&self.crossings(ray))
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: f64) -> Vector3 {
+        let local = *hit_point - self.base;
+        if self.is_cap(&local) {
+            return -UP;
+        }
+        let k = self.radius / self.height;
+        let radial = (local.x * local.x + local.z * local.z).sqrt();
+        if radial < FACE_EPSILON {
+            return UP;
+        }
+        Vector3 {
+            x: local.x / radial,
+            y: k,
+            z: local.z / radial,
+        }
+        .normalize()
+    }
+
+    fn texture_coordinates(&self, hit_point: &Point, _: f64) -> TextureCoordinates {
+        let local = *hit_point - self.base;
+        if self.is_cap(&local) {
+            TextureCoordinates {
+                x: (0.5 + 0.5 * local.x / self.radius) as f32,
+                y: (0.5 + 0.5 * local.z / self.radius) as f32,
+            }
+        } else {
+            TextureCoordinates {
+                x: angle_around_y(&local),
+                y: (local.y / self.height) as f32,
+            }
+        }
+    }
+
+    fn tangent(&self, hit_point: &Point, _: f64) -> Vector3 {
+        let local = *hit_point - self.base;
+        if self.is_cap(&local) {
+            Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            }
+        } else {
+            around_y_tangent(&local)
+        }
+    }
+}
+
+/// A torus lying in the xz plane around `center`.
+#[derive(Debug)]
+pub struct Torus {
+    pub center: Point,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub material: Material,
+}
+
+impl Torus {
+    /// All distances where the ray crosses the surface, sorted.
+    pub fn roots(&self, ray: &Ray) -> Vec<f64> {
+        let o = ray.origin - self.center;
+        let d = ray.direction;
+
+        // only search where the ray is inside the bounding sphere
+        let bound = self.major_radius + self.minor_radius;
+        let (enter, exit) = match solve_quadratic(1.0, 2.0 * o.dot(&d), o.dot(&o) - bound * bound) {
+            Some(span) => span,
+            None => return Vec::new(),
+        };
+
+        let r2 = self.major_radius * self.major_radius;
+        let b = o.dot(&d);
+        let k = o.dot(&o) + r2 - self.minor_radius * self.minor_radius;
+        let coefficients = [
+            1.0,
+            4.0 * b,
+            4.0 * b * b + 2.0 * k - 4.0 * r2 * (d.x * d.x + d.z * d.z),
+            4.0 * b * k - 8.0 * r2 * (o.x * d.x + o.z * d.z),
+            k * k - 4.0 * r2 * (o.x * o.x + o.z * o.z),
+        ];
+        polynomial_roots(&coefficients, enter, exit)
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, ray: &Ray) -> Option<f64> {
+        self.roots(ray).into_iter().find(|t| *t >= 0.0)
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        self.roots(ray)
+            .chunks_exact(2)
+            .map(|pair| Interval {
+                enter: pair[0],
+                exit: pair[1],
+            })
+            .collect()
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: f64) -> Vector3 {
+        let p = *hit_point - self.center;
+        let sum = p.length_squared() + self.major_radius * self.major_radius
+            - self.minor_radius * self.minor_radius;
+        let r2 = self.major_radius * self.major_radius;
+        Vector3 {
+            x: p.x * (sum - 2.0 * r2),
+            y: p.y * sum,
+            z: p.z * (sum - 2.0 * r2),
+        }
+        .normalize()
+    }
+
+    fn texture_coordinates(&self, hit_point: &Point, _: f64) -> TextureCoordinates {
+        let p = *hit_point - self.center;
+        let radial = (p.x * p.x + p.z * p.z).sqrt() - self.major_radius;
+        TextureCoordinates {
+            x: angle_around_y(&p),
+            y: (1.0 + (p.y.atan2(radial) as f32) / std::f32::consts::PI) * 0.5,
+        }
+    }
+
+    fn tangent(&self, hit_point: &Point, _: f64) -> Vector3 {
+        around_y_tangent(&(*hit_point - self.center))
+    }
 }
@@ -1,11 +1,30 @@
 use crate::point::Point;
 use crate::scene::Scene;
 use crate::vector3::Vector3;
-use rand::prelude::*;
+
+/// Approximates the footprint of a ray as a cone, used for texture filtering.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RayCone {
+    pub width: f64,
+    pub spread: f64,
+}
+
+impl RayCone {
+    /// The cone after travelling `distance` along the ray.
+    pub fn at(&self, distance: f64) -> RayCone {
+        RayCone {
+            width: self.width + self.spread * distance,
+            spread: self.spread,
+        }
+    }
+}
 
 pub struct Ray {
     pub origin: Point,
     pub direction: Vector3,
+    pub cone: RayCone,
+    /// When the ray is sent, within the shutter interval.
+    pub time: f64,
 }
 
 impl Ray {
@@ -14,26 +33,49 @@ impl Ray {
         self.origin + t * self.direction
     }
 
-    pub fn create_prime(x: f64, y: f64, scene: &Scene) -> Self {
-        assert!(scene.width > scene.height);
+    pub fn with_cone(self, cone: RayCone) -> Ray {
+        Ray { cone, ..self }
+    }
+
+    pub fn with_time(self, time: f64) -> Ray {
+        Ray { time, ..self }
+    }
+
+    /// The ray continuing straight on past `hit_point`, like through an invisible surface.
+    pub fn pass_through(&self, hit_point: &Point, bias: f64) -> Ray {
+        Ray {
+            origin: *hit_point + self.direction * bias,
+            direction: self.direction,
+            cone: self.cone,
+            time: self.time,
+        }
+    }
 
+    pub fn create_prime(x: f64, y: f64, scene: &Scene) -> Self {
         let field_of_view_adjustment = (scene.fov.to_radians() / 2.0).tan();
 
         let aspect = (scene.width as f64) / (scene.height as f64);
         // translate the (width x height) to the (-1..1 x -1..1) range
         // take account for aspect ratio
-        let sensor_x = field_of_view_adjustment
-            * (aspect * (((x as f64 + 0.5) / scene.width as f64) * 2.0 - 1.0));
-        let sensor_y =
-            field_of_view_adjustment * (1.0 - ((y as f64 + 0.5) / scene.height as f64) * 2.0);
+        let sensor_x =
+            field_of_view_adjustment * (aspect * (((x + 0.5) / scene.width as f64) * 2.0 - 1.0));
+        let sensor_y = field_of_view_adjustment * (1.0 - ((y + 0.5) / scene.height as f64) * 2.0);
         Self {
             origin: scene.origin,
-            direction: Vector3 {
-                x: sensor_x,
-                y: sensor_y,
-                z: -1.5,
-            }
-            .normalize(),
+            direction: scene
+                .orientation
+                .rotate(Vector3 {
+                    x: sensor_x,
+                    y: sensor_y,
+                    z: -1.5,
+                })
+                .normalize(),
+            cone: RayCone {
+                width: 0.0,
+                // the angle subtended by a single pixel
+                spread: 2.0 * field_of_view_adjustment / (1.5 * scene.height as f64),
+            },
+            time: scene.shutter.open,
         }
     }
 
@@ -42,23 +84,56 @@ impl Ray {
         incident: Vector3,
         intersection: Point,
         bias: f64,
+        time: f64,
     ) -> Ray {
         Ray {
             origin: intersection + (normal * bias),
             direction: incident - (2.0 * incident.dot(&normal) * normal),
+            cone: RayCone::default(),
+            time,
+        }
+    }
+
+    /// A reflection ray blurred around the mirror direction by `roughness`, `sample` picking
+    /// the blur.
+    pub fn create_glossy_reflection(
+        normal: Vector3,
+        incident: Vector3,
+        intersection: Point,
+        bias: f64,
+        roughness: f64,
+        sample: (f64, f64),
+        time: f64,
+    ) -> Ray {
+        let mirror = Ray::create_reflection(normal, incident, intersection, bias, time);
+        if roughness <= 0.0 {
+            return mirror;
+        }
+        let fuzz = Ray::create_scatter(&intersection, sample, time).direction * roughness;
+        let direction = (mirror.direction + fuzz).normalize();
+        if direction.dot(&normal) <= 0.0 {
+            return mirror;
+        }
+        Ray {
+            direction,
+            ..mirror
         }
     }
 
-    pub fn create_scatter(hit_point: &Point) -> Ray {
-        let mut rng = rand::thread_rng();
+    /// A ray in a uniformly distributed direction, picked by `sample`.
+    pub fn create_scatter(hit_point: &Point, sample: (f64, f64), time: f64) -> Ray {
+        let z = 1.0 - 2.0 * sample.0;
+        let radius = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * sample.1;
         Ray {
             origin: *hit_point,
             direction: Vector3 {
-                x: rng.gen_range(-1.0..=1.0),
-                y: rng.gen_range(-1.0..=1.0),
-                z: rng.gen_range(-1.0..=1.0),
-            }
-            .normalize(),
+                x: radius * phi.cos(),
+                y: radius * phi.sin(),
+                z,
+            },
+            cone: RayCone::default(),
+            time,
         }
     }
 
@@ -68,6 +143,7 @@ impl Ray {
         intersection: Point,
         bias: f64,
         index: f32,
+        time: f64,
     ) -> Option<Ray> {
         let mut ref_n = normal;
         let mut n_t = index as f64;
@@ -89,6 +165,8 @@ impl Ray {
             Some(Ray {
                 origin: intersection + (ref_n * -bias),
                 direction: (incident + i_dot_n * ref_n) * eta - ref_n * k.sqrt(),
+                cone: RayCone::default(),
+                time,
             })
         }
     }
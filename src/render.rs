@@ -1,30 +1,840 @@
+use crate::animation::Animation;
+use crate::checkpoint;
 use crate::color::Color;
+use crate::error::{Error, Result};
+use crate::post::{self, PostEffect};
+use crate::primitives::{Hittable, SurfaceType};
 use crate::ray::Ray;
-use crate::scene::{cast_ray, Scene};
-use image::{DynamicImage, GenericImage};
-use rand::prelude::*;
-
-// Antialiasing
-const SAMPLE_PER_PIXEL: u64 = 8;
-
-pub fn render(scene: &Scene) -> DynamicImage {
-    let mut img = DynamicImage::new_rgb8(scene.width, scene.height);
-    let mut rng = rand::thread_rng();
-    for x in 0..scene.width {
-        for y in 0..scene.height {
-            let mut color = Color {
-                red: 0.,
-                green: 0.,
-                blue: 0.,
-            };
-            for _ in 0..SAMPLE_PER_PIXEL {
-                let x_bias: f64 = rng.gen();
-                let y_bias: f64 = rng.gen();
-                let ray = Ray::create_prime(x as f64 + x_bias, y as f64 + y_bias, scene);
-                color = color + cast_ray(scene, &ray, 0);
+use crate::sampler::{CameraSample, Dimension, PixelSample};
+use crate::scene::{cast_ray, Scene, BLACK};
+use crate::stats::{self, Counter, RenderStats};
+use crate::vector3::Vector3;
+use image::{DynamicImage, GenericImage, Rgb, RgbImage};
+use rayon::prelude::*;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Rows rendered before their samples are splatted
+const BAND_HEIGHT: u32 = 32;
+
+// Primary rays per pixel for the auxiliary outputs
+const AOV_SAMPLES: u32 = 4;
+
+/// How many samples are taken in each pixel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sampling {
+    /// Taken in every pixel, and the size of the batches taken after them.
+    pub min_samples: u32,
+    pub max_samples: u32,
+    /// Pixels get more samples while the standard error of their mean luminance, relative
+    /// to it, is above this. With 0 every pixel whose samples differ at all gets
+    /// `max_samples`.
+    pub threshold: f32,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Sampling::fixed(8)
+    }
+}
+
+impl Sampling {
+    /// The same number of samples in every pixel.
+    pub fn fixed(samples: u32) -> Sampling {
+        Sampling {
+            min_samples: samples,
+            max_samples: samples,
+            threshold: 0.0,
+        }
+    }
+
+    /// Between `min_samples` and `max_samples`, until the error estimate drops below
+    /// `threshold`.
+    pub fn adaptive(min_samples: u32, max_samples: u32, threshold: f32) -> Sampling {
+        Sampling {
+            min_samples,
+            max_samples,
+            threshold,
+        }
+    }
+}
+
+/// A rectangle of the image to render alone, in pixels, e.g. to re-render part of a frame.
+/// The pixels come out the same as in a render of the whole image, so they can be patched in.
+/// The post-processing only sees the window though, so effects spreading light differ near
+/// its edges. Clipped to the image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CropWindow {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub output: CropOutput,
+}
+
+/// What images are returned for a `CropWindow`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CropOutput {
+    /// Just the window.
+    #[default]
+    Cropped,
+    /// The whole image, transparent outside of the window.
+    Full,
+}
+
+impl CropWindow {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> CropWindow {
+        CropWindow {
+            x,
+            y,
+            width,
+            height,
+            output: CropOutput::Cropped,
+        }
+    }
+
+    /// Tile `column`, `row` of a `width` by `height` image split into `columns` by `rows`
+    /// tiles as even as they get. None if the tile is outside of the grid, or empty because
+    /// there are more tiles than pixels.
+    pub fn tile(
+        width: u32,
+        height: u32,
+        column: u32,
+        row: u32,
+        columns: u32,
+        rows: u32,
+    ) -> Option<CropWindow> {
+        if column >= columns || row >= rows {
+            return None;
+        }
+        let split = |size: u32, i: u32, n: u32| (size as u64 * i as u64 / n as u64) as u32;
+        let (x, y) = (split(width, column, columns), split(height, row, rows));
+        let tile = CropWindow::new(
+            x,
+            y,
+            split(width, column + 1, columns) - x,
+            split(height, row + 1, rows) - y,
+        );
+        if tile.width == 0 || tile.height == 0 {
+            return None;
+        }
+        Some(tile)
+    }
+}
+
+/// The part of the image rendered, as `x`, `y`, `width` and `height`.
+fn window(scene: &Scene) -> (u32, u32, u32, u32) {
+    match scene.crop {
+        Some(crop) => {
+            let (x, y) = (crop.x.min(scene.width), crop.y.min(scene.height));
+            (
+                x,
+                y,
+                crop.width.min(scene.width - x),
+                crop.height.min(scene.height - y),
+            )
+        }
+        None => (0, 0, scene.width, scene.height),
+    }
+}
+
+/// The crop window and the pixels around it whose samples reach into it.
+fn film_region(scene: &Scene) -> (u32, u32, u32, u32) {
+    let reach = scene.filter.radius.ceil() as u32;
+    let (x, y, width, height) = window(scene);
+    let (left, top) = (x.saturating_sub(reach), y.saturating_sub(reach));
+    let right = (x + width + reach).min(scene.width);
+    let bottom = (y + height + reach).min(scene.height);
+    (left, top, right - left, bottom - top)
+}
+
+/// The window as the whole image if the scene asks for it, see `CropOutput`.
+fn place(scene: &Scene, image: DynamicImage) -> DynamicImage {
+    match scene.crop {
+        Some(CropWindow {
+            output: CropOutput::Full,
+            ..
+        }) => {
+            let (x, y, _, _) = window(scene);
+            let mut full = DynamicImage::new_rgba8(scene.width, scene.height);
+            full.copy_from(&image, x, y)
+                .expect("The window is within the image.");
+            full
+        }
+        _ => image,
+    }
+}
+
+/// How far `render_with_progress` got.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Progress {
+    /// Adaptive sampling takes more passes over the image after the first, each over the
+    /// pixels that are still too noisy.
+    pub pass: u32,
+    /// In the current pass, of `pixels`.
+    pub pixels_done: u64,
+    /// Including those around a crop window whose samples reach into it.
+    pub pixels: u64,
+    /// Camera rays, over all passes.
+    pub samples: u64,
+    pub elapsed: Duration,
+    pub samples_per_second: f64,
+    /// Until the current pass is done, estimated once some of it is.
+    pub remaining: Option<Duration>,
+}
+
+/// Cancels the renders it is passed to, also from other threads.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// When a progressive render stops, and where it shows how far it got. It stops at the first
+/// limit reached, so at least one has to be set.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Progressive {
+    pub time: Option<Duration>,
+    /// Pixels stop getting samples once their error estimate (see `Sampling`) is below this,
+    /// and the render once all of them have.
+    pub noise: Option<f32>,
+    pub max_samples: Option<u32>,
+    /// The image so far is written here every `snapshot_interval`.
+    pub snapshot_path: Option<PathBuf>,
+    /// The samples so far are written here every `snapshot_interval` and when the render
+    /// stops. If the file exists at the start, the render resumes from it.
+    pub checkpoint_path: Option<PathBuf>,
+    pub snapshot_interval: Duration,
+}
+
+/// The color seen by a camera ray through `x`, `y` on the image plane, in pixels.
+struct Sample {
+    x: f64,
+    y: f64,
+    color: Color,
+}
+
+/// The sum of the weighted samples reaching a pixel, and of their weights.
+#[derive(Clone, Copy)]
+pub(crate) struct Accumulator {
+    pub color: Color,
+    pub weight: f32,
+}
+
+/// The running mean and variance of the luminance of a pixel's own samples, by Welford's
+/// algorithm.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct PixelStatistics {
+    pub count: u32,
+    pub mean: f64,
+    pub squares: f64,
+}
+
+impl PixelStatistics {
+    fn add(&mut self, color: &Color) {
+        let luminance = color.luminance() as f64;
+        self.count += 1;
+        let delta = luminance - self.mean;
+        self.mean += delta / self.count as f64;
+        self.squares += delta * (luminance - self.mean);
+    }
+
+    /// The standard error of the mean relative to it, guarded against dark pixels.
+    fn relative_error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        let variance = self.squares / (self.count - 1) as f64;
+        (variance / self.count as f64).sqrt() / self.mean.max(1e-3)
+    }
+}
+
+/// The image being rendered, in floating point. Covers the crop window and the pixels around
+/// it whose samples reach into it, `x`, `y` being its corner in the image.
+pub(crate) struct Film {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Accumulator>,
+    pub statistics: Vec<PixelStatistics>,
+    /// Of the passes so far.
+    pub stats: RenderStats,
+}
+
+impl Film {
+    pub fn new(scene: &Scene) -> Film {
+        let (x, y, width, height) = film_region(scene);
+        let (width, height) = (width as usize, height as usize);
+        Film {
+            x: x as usize,
+            y: y as usize,
+            width,
+            height,
+            pixels: vec![
+                Accumulator {
+                    color: BLACK,
+                    weight: 0.0,
+                };
+                width * height
+            ],
+            statistics: vec![PixelStatistics::default(); width * height],
+            stats: RenderStats::default(),
+        }
+    }
+
+    /// Take the number of samples `wanted` asks for in every pixel, splatting them into the
+    /// pixels the reconstruction filter reaches. They are stratified in batches of `batch`.
+    /// Rows are rendered in parallel. The noise only depends on the scene seed, the pixel and
+    /// the sample, and the samples are always added up in the same order, so the image is the
+    /// same for any number of threads.
+    /// Before each band `proceed` is told the pixels done and the samples taken so far, and
+    /// the pass stops if it returns false, leaving the rest of the pixels with fewer samples.
+    /// Returns the number of samples taken.
+    fn add_pass(
+        &mut self,
+        scene: &Scene,
+        batch: u32,
+        wanted: impl Fn(&PixelStatistics) -> u32 + Sync,
+        mut proceed: impl FnMut(usize, u64) -> bool,
+    ) -> u64 {
+        let width = self.width;
+        let reach = scene.filter.radius.ceil() as usize;
+        let mut taken = 0;
+        let (left, top) = (self.x as u32, self.y as u32);
+        let tests_per_trace = stats::tests_per_trace(&scene.elements);
+        for band in (0..self.height).step_by(BAND_HEIGHT as usize) {
+            if !proceed(band * width, taken) {
+                break;
+            }
+            let rows = band..(band + BAND_HEIGHT as usize).min(self.height);
+            let sampling = Instant::now();
+            let rendered: Vec<(Vec<Sample>, RenderStats)> = self.statistics
+                [rows.start * width..rows.end * width]
+                .par_chunks_mut(width)
+                .zip(rows.clone())
+                .map(|(statistics, y)| {
+                    // whatever was counted on this thread outside of a pass
+                    stats::take(&tests_per_trace);
+                    let mut samples = Vec::new();
+                    for (x, pixel) in statistics.iter_mut().enumerate() {
+                        let first = pixel.count;
+                        for index in first..first + wanted(pixel) {
+                            let (x, y) = (left + x as u32, top + y as u32);
+                            let sample = render_sample(scene, x, y, index, batch);
+                            pixel.add(&sample.color);
+                            samples.push(sample);
+                        }
+                    }
+                    (samples, stats::take(&tests_per_trace))
+                })
+                .collect();
+            let mut samples = Vec::with_capacity(rendered.len());
+            for (row, stats) in rendered {
+                taken += row.len() as u64;
+                samples.push(row);
+                self.stats += stats;
             }
-            img.put_pixel(x, y, (color / SAMPLE_PER_PIXEL as u8).to_rgba());
+            self.stats.sampling_time += sampling.elapsed();
+
+            let splatting = Instant::now();
+
+            let first = rows.start.saturating_sub(reach);
+            let last = (rows.end + reach).min(self.height);
+            self.pixels[first * width..last * width]
+                .par_chunks_mut(width)
+                .enumerate()
+                .for_each(|(i, row)| {
+                    let y = first + i;
+                    for (offset, samples) in samples.iter().enumerate() {
+                        // samples stay within a pixel of their row
+                        if (rows.start + offset).abs_diff(y) > reach + 1 {
+                            continue;
+                        }
+                        for sample in samples {
+                            splat(scene, sample, left as usize, top as usize + y, row);
+                        }
+                    }
+                });
+            self.stats.splatting_time += splatting.elapsed();
+        }
+        taken
+    }
+
+    /// The values of the pixels in the crop window of the scene, row by row.
+    fn crop<T: Copy>(&self, scene: &Scene, values: &[T]) -> Vec<T> {
+        let (x, y, width, height) = window(scene);
+        let left = x as usize - self.x;
+        (y as usize - self.y..(y + height) as usize - self.y)
+            .flat_map(|row| &values[row * self.width + left..][..width as usize])
+            .copied()
+            .collect()
+    }
+
+    /// The weighted average of the samples reaching each pixel in the crop window.
+    fn colors(&self, scene: &Scene) -> Vec<Color> {
+        self.crop(scene, &self.pixels)
+            .iter()
+            .map(|pixel| {
+                if pixel.weight > 0.0 {
+                    pixel.color * (1.0 / pixel.weight)
+                } else {
+                    BLACK
+                }
+            })
+            .collect()
+    }
+
+    /// With the post-processing of the scene.
+    fn image(&self, scene: &Scene) -> DynamicImage {
+        let (_, _, width, height) = window(scene);
+        let colors = self.colors(scene);
+        let colors = post::post_process(&scene.post, width, height, colors, scene.seed);
+        place(scene, to_image(width, height, &colors))
+    }
+
+    /// The samples taken in each pixel, from blue for `min` through green and yellow to red
+    /// for `max`.
+    fn heatmap(&self, scene: &Scene, min: u32, max: u32) -> DynamicImage {
+        let stops = [
+            [0.0, 0.0, 0.5],
+            [0.0, 0.8, 0.2],
+            [1.0, 0.9, 0.0],
+            [0.9, 0.0, 0.0],
+        ];
+        let (_, _, width, height) = window(scene);
+        let mut img = RgbImage::new(width, height);
+        for (i, pixel) in self.crop(scene, &self.statistics).iter().enumerate() {
+            let t = if max > min {
+                (pixel.count.saturating_sub(min) as f32 / (max - min) as f32).min(1.0)
+            } else {
+                0.0
+            };
+            let position = t * (stops.len() - 1) as f32;
+            let stop = (position as usize).min(stops.len() - 2);
+            let f = position - stop as f32;
+            let channel = |c: usize| {
+                let value = stops[stop][c] * (1.0 - f) + stops[stop + 1][c] * f;
+                (value * 255.0) as u8
+            };
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            img.put_pixel(x, y, Rgb([channel(0), channel(1), channel(2)]));
         }
+        place(scene, DynamicImage::ImageRgb8(img))
+    }
+}
+
+/// A rendered image in linear floating point, with auxiliary outputs describing what the
+/// camera rays hit first, for guiding post-processing like `Denoiser`.
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub color: Vec<Color>,
+    /// The surface color, black where nothing was hit.
+    pub albedo: Vec<Color>,
+    /// The shading normal facing the camera, zero where nothing was hit.
+    pub normal: Vec<Vector3>,
+    /// The distance along the camera ray, infinite where nothing was hit.
+    pub depth: Vec<f64>,
+}
+
+impl Framebuffer {
+    pub fn to_image(&self) -> DynamicImage {
+        to_image(self.width, self.height, &self.color)
+    }
+
+    /// Apply the `effects` to the color, e.g. `Scene::post` after denoising.
+    pub fn post_process(&mut self, effects: &[PostEffect], seed: u64) {
+        let color = std::mem::take(&mut self.color);
+        self.color = post::post_process(effects, self.width, self.height, color, seed);
+    }
+}
+
+fn to_image(width: u32, height: u32, colors: &[Color]) -> DynamicImage {
+    let mut img = DynamicImage::new_rgb8(width, height);
+    for (i, color) in colors.iter().enumerate() {
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        img.put_pixel(x, y, color.to_rgba());
     }
     img
 }
+
+/// Fails if the scene can't be rendered, see `Scene::validate`.
+pub fn render(scene: &Scene) -> Result<DynamicImage> {
+    Ok(render_film(scene, |_, _, _| true)?.image(scene))
+}
+
+/// Like `render`, also counting what the render did.
+pub fn render_with_stats(scene: &Scene) -> Result<(DynamicImage, RenderStats)> {
+    Ok(finish(scene, render_film(scene, |_, _, _| true)?))
+}
+
+fn finish(scene: &Scene, film: Film) -> (DynamicImage, RenderStats) {
+    let start = Instant::now();
+    let image = film.image(scene);
+    let mut stats = film.stats;
+    stats.post_processing_time = start.elapsed();
+    (image, stats)
+}
+
+/// Like `render`, but keeps the image in floating point and adds the auxiliary outputs.
+/// The post-processing is left out, so it can follow denoising, see `Framebuffer::post_process`.
+/// Only covers the crop window, if any.
+pub fn render_framebuffer(scene: &Scene) -> Result<Framebuffer> {
+    let color = render_film(scene, |_, _, _| true)?.colors(scene);
+    let (left, top, width, height) = window(scene);
+    let aovs: Vec<(Color, Vector3, f64)> = (top..top + height)
+        .into_par_iter()
+        .flat_map_iter(|y| (left..left + width).map(move |x| render_aovs(scene, x, y)))
+        .collect();
+    Ok(Framebuffer {
+        width,
+        height,
+        color,
+        albedo: aovs.iter().map(|a| a.0).collect(),
+        normal: aovs.iter().map(|a| a.1).collect(),
+        depth: aovs.iter().map(|a| a.2).collect(),
+    })
+}
+
+/// Also returns a heatmap of the samples taken in each pixel, see `Sampling`.
+pub fn render_with_heatmap(scene: &Scene) -> Result<(DynamicImage, DynamicImage)> {
+    let film = render_film(scene, |_, _, _| true)?;
+    let sampling = scene.sampling;
+    Ok((
+        film.image(scene),
+        film.heatmap(scene, sampling.min_samples, sampling.max_samples),
+    ))
+}
+
+/// Every pixel gets the minimum number of samples, then batches more while its error estimate
+/// is above the threshold. `proceed` gets the pass, the pixels done in it and the samples
+/// taken in all, see `Film::add_pass`.
+fn render_film(scene: &Scene, mut proceed: impl FnMut(u32, usize, u64) -> bool) -> Result<Film> {
+    scene.validate()?;
+    let sampling = scene.sampling;
+    let batch = sampling.min_samples.max(1);
+    let max_samples = sampling.max_samples.max(batch);
+    let mut film = Film::new(scene);
+    let mut samples = film.add_pass(
+        scene,
+        batch,
+        |_| batch,
+        |pixels, taken| proceed(0, pixels, taken),
+    );
+    if max_samples == batch {
+        return Ok(film);
+    }
+    let wanted = |pixel: &PixelStatistics| {
+        if pixel.relative_error() > sampling.threshold as f64 {
+            batch.min(max_samples - pixel.count)
+        } else {
+            0
+        }
+    };
+    for pass in 1.. {
+        let taken = film.add_pass(scene, batch, wanted, |pixels, taken| {
+            proceed(pass, pixels, samples + taken)
+        });
+        if taken == 0 {
+            break;
+        }
+        samples += taken;
+    }
+    Ok(film)
+}
+
+/// Like `render_with_stats`, telling `report` how far it got between bands of rows. Fails with
+/// `Error::Cancelled` if `cancellation` is cancelled before the render is done, which stops it
+/// at the next band.
+pub fn render_with_progress(
+    scene: &Scene,
+    mut report: impl FnMut(&Progress),
+    cancellation: &CancellationToken,
+) -> Result<(DynamicImage, RenderStats)> {
+    let start = Instant::now();
+    let (_, _, width, height) = film_region(scene);
+    let mut progress = Progress {
+        pass: 0,
+        pixels_done: 0,
+        pixels: width as u64 * height as u64,
+        samples: 0,
+        elapsed: Duration::ZERO,
+        samples_per_second: 0.0,
+        remaining: None,
+    };
+    let mut pass_start = start;
+    let film = render_film(scene, |pass, pixels, samples| {
+        if pass != progress.pass {
+            progress.pass = pass;
+            pass_start = Instant::now();
+        }
+        progress.pixels_done = pixels as u64;
+        progress.samples = samples;
+        progress.elapsed = start.elapsed();
+        progress.samples_per_second = samples as f64 / progress.elapsed.as_secs_f64();
+        progress.remaining = (pixels > 0).then(|| {
+            let fraction = pixels as f64 / progress.pixels as f64;
+            pass_start.elapsed().mul_f64((1.0 - fraction) / fraction)
+        });
+        report(&progress);
+        !cancellation.is_cancelled()
+    })?;
+    if cancellation.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+    progress.pixels_done = progress.pixels;
+    progress.samples = film.statistics.iter().map(|s| s.count as u64).sum();
+    progress.elapsed = start.elapsed();
+    progress.samples_per_second = progress.samples as f64 / progress.elapsed.as_secs_f64();
+    progress.remaining = Some(Duration::ZERO);
+    report(&progress);
+    Ok(finish(scene, film))
+}
+
+/// Render one sample per pixel at a time, accumulating them, until a limit of `progressive`
+/// is reached. Whenever it stops, the image is the average of the samples taken so far, and
+/// the first pass is always finished, however short the time limit.
+/// The time limit counts from the start of this call, also when resuming from a checkpoint,
+/// and the sample limit includes the resumed samples. With a checkpoint the render only stops
+/// between passes, so resuming adds the samples up in the same order as an uninterrupted
+/// render, and ends with the same image. Cancelling `cancellation` stops the render like the
+/// time limit does, still writing the checkpoint and returning the image so far. Fails without
+/// any limit, or if the checkpoint belongs to a different scene, see `Scene::fingerprint`.
+pub fn render_progressive(
+    scene: &Scene,
+    progressive: &Progressive,
+    cancellation: &CancellationToken,
+) -> Result<DynamicImage> {
+    scene.validate()?;
+    if progressive.time.is_none()
+        && progressive.noise.is_none()
+        && progressive.max_samples.is_none()
+    {
+        return Err(Error::Invalid(
+            "A progressive render needs a time, noise or sample limit.".to_string(),
+        ));
+    }
+    let start = Instant::now();
+    let deadline = progressive.time.map(|time| start + time);
+    let max_samples = progressive.max_samples.unwrap_or(u32::MAX);
+    let wanted = |pixel: &PixelStatistics| {
+        // the error is infinite before the second sample, the least to estimate it from
+        let noisy = progressive
+            .noise
+            .is_none_or(|noise| pixel.relative_error() > noise as f64);
+        (pixel.count < max_samples && noisy) as u32
+    };
+
+    // hashing the scene takes a while with large textures, so it's done once
+    let fingerprint = progressive
+        .checkpoint_path
+        .as_ref()
+        .map_or(0, |_| scene.fingerprint());
+    let mut film = match progressive.checkpoint_path {
+        Some(ref path) if path.exists() => checkpoint::load(scene, fingerprint, path)?,
+        _ => Film::new(scene),
+    };
+    let mut last_snapshot = start;
+    // the first pass is always finished, so even a tiny time limit leaves no pixel black
+    let mut first_pass = true;
+    // a pass resumed halfway would splat the samples of the pixels at its stopping point in a
+    // different order, which changes the sums
+    let whole_passes = progressive.checkpoint_path.is_some();
+    loop {
+        let before_deadline = |_, _| {
+            first_pass
+                || whole_passes
+                || (deadline.is_none_or(|d| Instant::now() < d) && !cancellation.is_cancelled())
+        };
+        if film.add_pass(scene, 1, wanted, before_deadline) == 0 {
+            break;
+        }
+        first_pass = false;
+        if deadline.is_some_and(|d| Instant::now() >= d) || cancellation.is_cancelled() {
+            break;
+        }
+        if last_snapshot.elapsed() >= progressive.snapshot_interval {
+            if let Some(ref path) = progressive.snapshot_path {
+                film.image(scene).save(path)?;
+            }
+            if let Some(ref path) = progressive.checkpoint_path {
+                checkpoint::save(&film, scene, fingerprint, path)?;
+            }
+            last_snapshot = Instant::now();
+        }
+    }
+    if let Some(ref path) = progressive.checkpoint_path {
+        checkpoint::save(&film, scene, fingerprint, path)?;
+    }
+    Ok(film.image(scene))
+}
+
+/// Add the sample to the pixels of row `y` within the filter radius, the row starting at
+/// column `left`.
+fn splat(scene: &Scene, sample: &Sample, left: usize, y: usize, row: &mut [Accumulator]) {
+    let radius = scene.filter.radius;
+    // pixel centers are at the middle of the pixels, a half-open interval around the sample
+    let dy = y as f64 + 0.5 - sample.y;
+    if dy <= -radius || dy > radius {
+        return;
+    }
+    let sample_x = sample.x - left as f64;
+    let start = (sample_x - 0.5 - radius).floor().max(0.0) as usize;
+    let end = ((sample_x - 0.5 + radius).ceil().max(0.0) as usize).min(row.len() - 1);
+    for (x, pixel) in row.iter_mut().enumerate().take(end + 1).skip(start) {
+        let dx = x as f64 + 0.5 - sample_x;
+        if dx <= -radius || dx > radius {
+            continue;
+        }
+        let weight = scene.filter.weight(dx, dy) as f32;
+        pixel.color = pixel.color + sample.color * weight;
+        pixel.weight += weight;
+    }
+}
+
+/// The albedo, normal and depth of the pixel, averaged over the positions of its first
+/// samples. Invisible boundaries of media are looked through.
+fn render_aovs(scene: &Scene, x: u32, y: u32) -> (Color, Vector3, f64) {
+    let mut albedo = BLACK;
+    let mut normal = Vector3::zero();
+    let (mut depth, mut hits) = (0.0, 0);
+    for index in 0..AOV_SAMPLES {
+        let sample = CameraSample::new(PixelSample {
+            sampler: scene.sampler,
+            seed: scene.seed,
+            x,
+            y,
+            index,
+            count: AOV_SAMPLES,
+        });
+        let (x_bias, y_bias) = sample.get_2d(0, Dimension::Pixel);
+        let mut ray = Ray::create_prime(x as f64 + x_bias, y as f64 + y_bias, scene)
+            .with_time(scene.shutter.time(sample.get_1d(0, Dimension::Time)));
+        let mut travelled = 0.0;
+        for _ in 0..scene.max_recursion {
+            let intersection = match scene.trace(&ray) {
+                Some(i) => i,
+                None => break,
+            };
+            let element = intersection.element;
+            let hit = ray.at(intersection.distance);
+            travelled += intersection.distance;
+            if let SurfaceType::Boundary = element.material().surface {
+                ray = ray.pass_through(&hit, scene.shadow_bias);
+                continue;
+            }
+            let footprint = element.footprint(
+                &hit,
+                ray.time,
+                element.surface_normal(&hit, ray.time),
+                ray.direction,
+                ray.cone.at(travelled).width,
+            );
+            let shading_normal = element.shading_normal(&hit, ray.time).normalize();
+            albedo = albedo + element.color(&hit, ray.time, &footprint);
+            normal = normal
+                + if shading_normal.dot(&ray.direction) > 0.0 {
+                    -shading_normal
+                } else {
+                    shading_normal
+                };
+            depth += travelled;
+            hits += 1;
+            break;
+        }
+    }
+    let fraction = 1.0 / AOV_SAMPLES as f64;
+    (
+        albedo * fraction as f32,
+        normal * fraction,
+        if hits > 0 {
+            depth / hits as f64
+        } else {
+            f64::INFINITY
+        },
+    )
+}
+
+/// Sample `index` of the pixel, taken in batches of `batch`.
+fn render_sample(scene: &Scene, x: u32, y: u32, index: u32, batch: u32) -> Sample {
+    let sample = CameraSample::new(PixelSample {
+        sampler: scene.sampler,
+        seed: scene.seed,
+        x,
+        y,
+        index,
+        count: batch,
+    });
+    let (x_bias, y_bias) = sample.get_2d(0, Dimension::Pixel);
+    let (sample_x, sample_y) = (x as f64 + x_bias, y as f64 + y_bias);
+    let ray = Ray::create_prime(sample_x, sample_y, scene)
+        .with_time(scene.shutter.time(sample.get_1d(0, Dimension::Time)));
+    stats::count(Counter::CameraRay);
+    let mut color = cast_ray(scene, &ray, 0, &sample);
+    stats::end_path();
+    // one degenerate path would spoil every pixel it is splatted into
+    if !color.is_finite() {
+        stats::count(Counter::InvalidSample);
+        color = BLACK;
+    }
+    Sample {
+        x: sample_x,
+        y: sample_y,
+        color,
+    }
+}
+
+/// Render and save every frame in `frames`. The run of `#` in `path` is replaced by the
+/// zero padded frame number, e.g. `frames/turntable_####.png`, or the number is appended to
+/// the file stem if there is none.
+pub fn render_sequence(
+    scene: &mut Scene,
+    animation: &Animation,
+    frames: Range<u32>,
+    path: &str,
+) -> Result<()> {
+    for frame in frames {
+        animation.apply(scene, frame)?;
+        render(scene)?.save(frame_path(path, frame))?;
+    }
+    Ok(())
+}
+
+fn frame_path(path: &str, frame: u32) -> String {
+    match path.find('#') {
+        Some(start) => {
+            let width = path[start..].chars().take_while(|&c| c == '#').count();
+            format!(
+                "{}{:0width$}{}",
+                &path[..start],
+                frame,
+                &path[start + width..],
+                width = width
+            )
+        }
+        None => match path.rfind('.') {
+            Some(dot) if !path[dot..].contains('/') => {
+                format!("{}_{:04}{}", &path[..dot], frame, &path[dot..])
+            }
+            _ => format!("{}_{:04}", path, frame),
+        },
+    }
+}
@@ -0,0 +1,453 @@
+use rand::{Error, RngCore};
+use std::cell::Cell;
+use std::sync::OnceLock;
+
+/// How the sample values of a pixel are spread over the dimensions they are used in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Sampler {
+    /// Uniform random values.
+    #[default]
+    Independent,
+    /// One jittered value in each stratum of the pixel's samples, 2D values on a grid.
+    Stratified,
+    /// The Halton sequence with random digit permutations per pixel.
+    Halton,
+    /// The Sobol sequence, Owen scrambled and shuffled per pixel.
+    Sobol,
+    /// Golden ratio sequences offset by a blue noise mask, so the error of neighbouring pixels
+    /// differs and looks like fine grain rather than blotches.
+    BlueNoise,
+}
+
+/// What sample values are used for. The camera ones come first, then every bounce of a path
+/// gets its own set, so the same decision is made with the same dimension in every sample.
+/// `Lens` and `Light` are set aside for depth of field and area lights, which can then come
+/// without moving the other dimensions and changing every seeded image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dimension {
+    /// 2D, the position in the pixel.
+    Pixel,
+    /// 2D, the position on the lens, for cameras with one.
+    Lens,
+    /// 1D, the time in the shutter interval.
+    Time,
+    /// 2D, a point on a light with an area.
+    Light,
+    /// 2D, the direction of a diffuse or glossy bounce.
+    Bsdf,
+    /// 2D, the direction to scatter in in a medium.
+    Phase,
+    /// 1D, the choice between the lobes of a material.
+    Lobe,
+}
+
+// five used, rounded up to keep the 2D dimensions at even indices
+const CAMERA_DIMENSIONS: u32 = 6;
+// seven used, rounded up likewise
+const BOUNCE_DIMENSIONS: u32 = 8;
+
+impl Dimension {
+    /// The index of the first dimension, 2D ones starting at even indices.
+    fn index(self, depth: u32) -> u32 {
+        let bounce = |offset: u32| CAMERA_DIMENSIONS + depth * BOUNCE_DIMENSIONS + offset;
+        match self {
+            Dimension::Pixel => 0,
+            Dimension::Lens => 2,
+            Dimension::Time => 4,
+            Dimension::Light => bounce(0),
+            Dimension::Bsdf => bounce(2),
+            Dimension::Phase => bounce(4),
+            Dimension::Lobe => bounce(6),
+        }
+    }
+}
+
+/// The camera sample being traced.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PixelSample {
+    pub sampler: Sampler,
+    pub seed: u64,
+    pub x: u32,
+    pub y: u32,
+    pub index: u32,
+    /// The number of samples taken in the pixel.
+    pub count: u32,
+}
+
+/// The sample values of a camera sample, handed down along its path.
+pub struct CameraSample {
+    pixel: PixelSample,
+    /// The number of streams taken so far.
+    streams: Cell<u64>,
+}
+
+impl CameraSample {
+    pub(crate) fn new(pixel: PixelSample) -> CameraSample {
+        CameraSample {
+            pixel,
+            streams: Cell::new(0),
+        }
+    }
+
+    pub fn get_1d(&self, depth: u32, dimension: Dimension) -> f64 {
+        self.pixel.value(dimension.index(depth))
+    }
+
+    pub fn get_2d(&self, depth: u32, dimension: Dimension) -> (f64, f64) {
+        self.pixel.value_2d(dimension.index(depth))
+    }
+
+    /// Random numbers for decisions without a fixed dimension, like the steps of delta tracking.
+    /// Every stream of the sample is different, but the same in every run.
+    pub fn stream(&self) -> SampleRng {
+        let stream = self.streams.replace(self.streams.get() + 1);
+        let pixel = &self.pixel;
+        SampleRng {
+            state: hash(&[pixel.seed, pixel.pixel(), pixel.index as u64, !stream]),
+        }
+    }
+}
+
+impl PixelSample {
+    fn pixel(&self) -> u64 {
+        (self.x as u64) << 32 | self.y as u64
+    }
+
+    fn hash(&self, values: &[u64]) -> u64 {
+        values
+            .iter()
+            .fold(hash(&[self.seed, self.pixel()]), |h, &v| mix(h ^ mix(v)))
+    }
+
+    fn independent(&self, dimension: u32) -> f64 {
+        to_unit(self.hash(&[self.index as u64, dimension as u64]))
+    }
+
+    /// The mask shifted by a different offset for every dimension.
+    fn blue_noise(&self, dimension: u32) -> f64 {
+        let offset = self.hash(&[dimension as u64]);
+        blue_noise(
+            self.x.wrapping_add(offset as u32),
+            self.y.wrapping_add((offset >> 32) as u32),
+        )
+    }
+
+    fn value(&self, dimension: u32) -> f64 {
+        match self.sampler {
+            Sampler::Independent => self.independent(dimension),
+            Sampler::Stratified => {
+                let count = self.count.max(1);
+                let (round, index) = (self.index / count, self.index % count);
+                let permutation = self.hash(&[round as u64, dimension as u64]);
+                let stratum = permute(index, count, permutation as u32);
+                (stratum as f64 + self.independent(dimension)) / count as f64
+            }
+            Sampler::Halton => match PRIMES.get(dimension as usize) {
+                Some(&base) => {
+                    let permutation = self.hash(&[dimension as u64]);
+                    scrambled_radical_inverse(self.index, base, permutation)
+                }
+                None => self.independent(dimension),
+            },
+            Sampler::Sobol => {
+                let seed = self.hash(&[(dimension / 4) as u64]) as u32;
+                sobol(self.index, dimension % 4, seed)
+            }
+            Sampler::BlueNoise => {
+                (self.blue_noise(dimension) + self.index as f64 * 0.618_033_988_749_895).fract()
+            }
+        }
+    }
+
+    fn value_2d(&self, dimension: u32) -> (f64, f64) {
+        match self.sampler {
+            Sampler::Stratified => {
+                let count = self.count.max(1);
+                let (round, index) = (self.index / count, self.index % count);
+                let columns = ((count as f64).sqrt().round() as u32).max(1);
+                let rows = count.div_ceil(columns);
+                let permutation = self.hash(&[round as u64, dimension as u64]);
+                let cell = permute(index, columns * rows, permutation as u32);
+                (
+                    ((cell % columns) as f64 + self.independent(dimension)) / columns as f64,
+                    ((cell / columns) as f64 + self.independent(dimension + 1)) / rows as f64,
+                )
+            }
+            Sampler::BlueNoise => {
+                // the R2 sequence, the 2D counterpart of the golden ratio one
+                let index = self.index as f64;
+                (
+                    (self.blue_noise(dimension) + index * 0.754_877_666_246_693).fract(),
+                    (self.blue_noise(dimension + 1) + index * 0.569_840_290_998_053).fract(),
+                )
+            }
+            _ => (self.value(dimension), self.value(dimension + 1)),
+        }
+    }
+}
+
+/// Random numbers derived from the pixel sample, see `stream`.
+pub struct SampleRng {
+    state: u64,
+}
+
+impl RngCore for SampleRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix(self.state)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// The SplitMix64 finalizer.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+pub(crate) fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(0x243f_6a88_85a3_08d3, |h, &v| mix(h ^ mix(v)))
+}
+
+/// In 0..1, from the top 53 bits.
+pub(crate) fn to_unit(x: u64) -> f64 {
+    (x >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+}
+
+/// Element `index` of a random permutation of 0..`length`, picked by `seed`.
+/// Kensler's hashed permutation, cycle walking to the next power of two.
+fn permute(mut index: u32, length: u32, seed: u32) -> u32 {
+    if length <= 1 {
+        return 0;
+    }
+    let mut w = length - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        index ^= seed;
+        index = index.wrapping_mul(0xe170_893d);
+        index ^= seed >> 16;
+        index ^= (index & w) >> 4;
+        index ^= seed >> 8;
+        index = index.wrapping_mul(0x0929_eb3f);
+        index ^= seed >> 23;
+        index ^= (index & w) >> 1;
+        index = index.wrapping_mul(1 | seed >> 27);
+        index = index.wrapping_mul(0x6935_fa69);
+        index ^= (index & w) >> 11;
+        index = index.wrapping_mul(0x74dc_b303);
+        index ^= (index & w) >> 2;
+        index = index.wrapping_mul(0x9e50_1cc3);
+        index ^= (index & w) >> 2;
+        index = index.wrapping_mul(0xc860_a3df);
+        index &= w;
+        index ^= index >> 5;
+        if index < length {
+            return (index.wrapping_add(seed)) % length;
+        }
+    }
+}
+
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+/// The digits of `index` in `base` mirrored around the decimal point, each digit position
+/// with its own permutation of the digits. Runs over 32 bits worth of digits, as permuted
+/// zeros beyond the last digit of the index aren't zero anymore.
+fn scrambled_radical_inverse(mut index: u32, base: u32, seed: u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut scale = inverse_base;
+    let mut result = 0.0;
+    let mut position = 0;
+    while scale * (1u64 << 32) as f64 > 1.0 {
+        let digit = index % base;
+        let permutation = hash(&[seed, position]) as u32;
+        result += permute(digit, base, permutation) as f64 * scale;
+        index /= base;
+        scale *= inverse_base;
+        position += 1;
+    }
+    result.min(1.0 - f64::EPSILON / 2.0)
+}
+
+/// The direction numbers of the first four Sobol dimensions, from the primitive polynomials
+/// x + 1, x^2 + x + 1 and x^3 + x + 1 with the initial numbers of Joe and Kuo.
+const SOBOL_DIRECTIONS: [[u32; 32]; 4] = {
+    let mut directions = [[0u32; 32]; 4];
+    let mut bit = 0;
+    while bit < 32 {
+        directions[0][bit] = 1 << (31 - bit);
+        bit += 1;
+    }
+    // (degree, coefficients, initial numbers)
+    let polynomials: [(usize, u32, [u32; 3]); 3] =
+        [(1, 0, [1, 0, 0]), (2, 1, [1, 3, 0]), (3, 1, [1, 3, 1])];
+    let mut dimension = 1;
+    while dimension < 4 {
+        let (degree, coefficients, initial) = polynomials[dimension - 1];
+        let mut v = [0u32; 32];
+        let mut i = 0;
+        while i < 32 {
+            if i < degree {
+                v[i] = initial[i] << (31 - i);
+            } else {
+                let mut value = v[i - degree] ^ (v[i - degree] >> degree);
+                let mut k = 1;
+                while k < degree {
+                    if (coefficients >> (degree - 1 - k)) & 1 == 1 {
+                        value ^= v[i - k];
+                    }
+                    k += 1;
+                }
+                v[i] = value;
+            }
+            i += 1;
+        }
+        directions[dimension] = v;
+        dimension += 1;
+    }
+    directions
+};
+
+/// Owen scrambling as a hash over the reversed bits, after Burley's "Practical Hash-based
+/// Owen Scrambling".
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+/// One of four dimensions of the shuffled and scrambled Sobol sequence picked by `seed`.
+/// The shuffle is shared by the four, so they stay a consistent 4D point.
+fn sobol(index: u32, dimension: u32, seed: u32) -> f64 {
+    let index = nested_uniform_scramble(index, seed);
+    let directions = &SOBOL_DIRECTIONS[dimension as usize];
+    let value = (0..32)
+        .filter(|&bit| (index >> bit) & 1 == 1)
+        .fold(0, |value, bit| value ^ directions[bit]);
+    let scramble_seed = hash(&[seed as u64, dimension as u64]) as u32;
+    nested_uniform_scramble(value, scramble_seed) as f64 / (1u64 << 32) as f64
+}
+
+const MASK_SIZE: usize = 64;
+
+/// A tileable blue noise mask of ranks in 0..1, made once by void-and-cluster.
+fn blue_noise(x: u32, y: u32) -> f64 {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    let mask = MASK.get_or_init(void_and_cluster);
+    let (x, y) = (x as usize % MASK_SIZE, y as usize % MASK_SIZE);
+    mask[y * MASK_SIZE + x]
+}
+
+/// Ulichney's void-and-cluster method: starting from a random pattern relaxed into an even
+/// one, points are ranked by removing the most clustered ones and filling the largest voids.
+fn void_and_cluster() -> Vec<f64> {
+    let size = MASK_SIZE;
+    let cells = size * size;
+    let sigma = 1.5f64;
+    let radius = 6i64;
+    let mut kernel = Vec::new();
+    for dy in -radius..=radius {
+        for dx in -radius..=radius {
+            let weight = (-((dx * dx + dy * dy) as f64) / (2.0 * sigma * sigma)).exp();
+            kernel.push((dx, dy, weight));
+        }
+    }
+    let update = |energy: &mut Vec<f64>, cell: usize, sign: f64| {
+        let (x, y) = ((cell % size) as i64, (cell / size) as i64);
+        for &(dx, dy, weight) in &kernel {
+            let nx = (x + dx).rem_euclid(size as i64) as usize;
+            let ny = (y + dy).rem_euclid(size as i64) as usize;
+            energy[ny * size + nx] += sign * weight;
+        }
+    };
+    let tightest_cluster = |points: &[bool], energy: &[f64]| {
+        (0..cells)
+            .filter(|&c| points[c])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+    let largest_void = |points: &[bool], energy: &[f64]| {
+        (0..cells)
+            .filter(|&c| !points[c])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    // a fixed random initial pattern, so the mask is the same in every run
+    let mut points = vec![false; cells];
+    let mut energy = vec![0.0; cells];
+    let initial = cells / 10;
+    let mut placed = 0;
+    let mut counter = 0u64;
+    while placed < initial {
+        let cell = (hash(&[counter]) % cells as u64) as usize;
+        counter += 1;
+        if !points[cell] {
+            points[cell] = true;
+            update(&mut energy, cell, 1.0);
+            placed += 1;
+        }
+    }
+    loop {
+        let cluster = tightest_cluster(&points, &energy);
+        points[cluster] = false;
+        update(&mut energy, cluster, -1.0);
+        let void = largest_void(&points, &energy);
+        points[void] = true;
+        update(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0usize; cells];
+    let (prototype, prototype_energy) = (points.clone(), energy.clone());
+    for rank in (0..initial).rev() {
+        let cluster = tightest_cluster(&points, &energy);
+        points[cluster] = false;
+        update(&mut energy, cluster, -1.0);
+        ranks[cluster] = rank;
+    }
+    points = prototype;
+    energy = prototype_energy;
+    for rank in initial..cells {
+        let void = largest_void(&points, &energy);
+        points[void] = true;
+        update(&mut energy, void, 1.0);
+        ranks[void] = rank;
+    }
+    ranks
+        .iter()
+        .map(|&rank| (rank as f64 + 0.5) / cells as f64)
+        .collect()
+}
@@ -1,51 +1,349 @@
 use crate::color::Color;
+use crate::error::{Error, Result};
+use crate::filter::PixelFilter;
+use crate::integrator::{trace_path, Integrator};
 use crate::light::Light;
+use crate::medium::{Flight, Medium};
+use crate::motion::Shutter;
 use crate::point::Point;
+use crate::post::PostEffect;
 use crate::primitives::{Element, Hittable, Intersection, SurfaceType};
-use crate::ray::Ray;
+use crate::ray::{Ray, RayCone};
+use crate::render::{CropWindow, Sampling};
+use crate::sampler::{CameraSample, Dimension, Sampler};
+use crate::stats::{self, Counter};
+use crate::texture::Footprint;
+use crate::transform::Quaternion;
 use crate::vector3::Vector3;
+use std::fmt::{self, Write};
 // use rand::prelude::*;
 
-const BLACK: Color = Color {
+pub(crate) const BLACK: Color = Color {
     red: 0.0,
     green: 0.0,
     blue: 0.0,
 };
 
+const WHITE: Color = Color {
+    red: 1.0,
+    green: 1.0,
+    blue: 1.0,
+};
+
+// Distances sampled along a ray for single scattering
+const SCATTERING_SAMPLES: u32 = 4;
+
+#[derive(Debug)]
 pub struct Scene {
     pub width: u32,
     pub height: u32,
     pub origin: Point,
     pub fov: f64,
+    /// The camera looks down -z, turned by this.
+    pub orientation: Quaternion,
     pub elements: Vec<Element>,
     pub lights: Vec<Light>,
     pub shadow_bias: f64,
     pub max_recursion: u32,
+    /// The medium filling the scene outside of any element, e.g. fog.
+    pub medium: Option<Medium>,
+    pub integrator: Integrator,
+    pub shutter: Shutter,
+    pub sampler: Sampler,
+    pub sampling: Sampling,
+    pub filter: PixelFilter,
+    /// Only this part of the image is rendered, see `CropWindow`.
+    pub crop: Option<CropWindow>,
+    /// Applied in order to the rendered image, see `PostEffect`.
+    pub post: Vec<PostEffect>,
+    /// All the noise is derived from it, so renders with the same seed are identical.
+    pub seed: u64,
+}
+
+/// FNV-1a over the text written into it.
+struct Fingerprint(u64);
+
+impl fmt::Write for Fingerprint {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        Ok(())
+    }
 }
 
 impl Scene {
-    pub fn trace(&self, ray: &Ray) -> Option<Intersection> {
+    /// A hash of everything in the scene that changes the samples, which stays the same
+    /// across runs. Textures, heightfields and volumes go in by their checksums. The post
+    /// effects and the `CropOutput` of a crop window are left out, as they only change the
+    /// image made from the samples. Render checkpoints only resume for the same fingerprint.
+    /// The hash is taken of the `{:?}` output, which isn't guaranteed to stay the same
+    /// between Rust versions, so after a compiler upgrade old checkpoints may not resume.
+    pub fn fingerprint(&self) -> u64 {
+        // taken apart, so a new field has to be decided on here
+        let Scene {
+            width,
+            height,
+            origin,
+            fov,
+            orientation,
+            elements,
+            lights,
+            shadow_bias,
+            max_recursion,
+            medium,
+            integrator,
+            shutter,
+            sampler,
+            sampling,
+            filter,
+            crop,
+            post: _,
+            seed,
+        } = self;
+        let crop = crop.map(|c| (c.x, c.y, c.width, c.height));
+        let mut fingerprint = Fingerprint(0xcbf2_9ce4_8422_2325);
+        write!(
+            fingerprint,
+            "{:?}{:?}{:?}",
+            (width, height, origin, fov, orientation, elements),
+            (lights, shadow_bias, max_recursion, medium, integrator),
+            (shutter, sampler, sampling, filter, crop, seed),
+        )
+        .expect("Hashing can't fail.");
+        fingerprint.0
+    }
+
+    /// Check that the scene can be rendered, which the renders do before they start.
+    /// Degenerate primitives are caught down through instances, CSG and moving elements, as
+    /// are surfaces without an inside used as CSG operands or filled with a medium.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| Err(Error::Invalid(message.to_string()));
+        let finite = |p: &Point| p.x.is_finite() && p.y.is_finite() && p.z.is_finite();
+        if self.width == 0 || self.height == 0 {
+            return invalid("The image can't be empty.");
+        }
+        if !(self.fov > 0.0 && self.fov < 180.0) {
+            return invalid("The field of view has to be between 0 and 180 degrees.");
+        }
+        if !finite(&self.origin) {
+            return invalid("The camera position has to be finite.");
+        }
+        let q = self.orientation;
+        let norm = q.w * q.w + q.x * q.x + q.y * q.y + q.z * q.z;
+        if !(norm.is_finite() && norm > 0.0) {
+            return invalid("The camera orientation has to be a finite, non-zero quaternion.");
+        }
+        if !(self.shadow_bias >= 0.0 && self.shadow_bias.is_finite()) {
+            return invalid("The shadow bias has to be finite and not negative.");
+        }
+        if !(self.shutter.open.is_finite()
+            && self.shutter.close.is_finite()
+            && self.shutter.open <= self.shutter.close)
+        {
+            return invalid("The shutter has to close after it opens.");
+        }
+        if self.sampling.threshold.is_nan() {
+            return invalid("The sampling threshold can't be NaN.");
+        }
+        if self.sampling.min_samples > self.sampling.max_samples {
+            return invalid("The minimum number of samples can't be above the maximum.");
+        }
+        // samples are up to half a pixel from the pixel center
+        if !(self.filter.radius >= 0.5 && self.filter.radius.is_finite()) {
+            return invalid("The filter radius has to be at least half a pixel.");
+        }
+        if let Some(crop) = self.crop {
+            if crop.x >= self.width || crop.y >= self.height || crop.width == 0 || crop.height == 0
+            {
+                return invalid("The crop window has to overlap the image.");
+            }
+        }
+        if let Some(message) = self.elements.iter().find_map(degenerate) {
+            return invalid(message);
+        }
+        if self
+            .elements
+            .iter()
+            .any(|e| e.material().medium.is_some() && !solid(e))
+        {
+            return invalid("Only solids can be filled with a medium.");
+        }
+        let bright = |threshold: f32, intensity: f32| {
+            threshold >= 0.0 && threshold.is_finite() && intensity.is_finite()
+        };
+        for effect in &self.post {
+            let valid = match *effect {
+                PostEffect::Bloom {
+                    threshold,
+                    intensity,
+                    ..
+                } => bright(threshold, intensity),
+                PostEffect::Glare {
+                    threshold,
+                    intensity,
+                    length,
+                    angle,
+                    ..
+                } => {
+                    bright(threshold, intensity)
+                        && length >= 0.0
+                        && length.is_finite()
+                        && angle.is_finite()
+                }
+                PostEffect::Vignette { strength } => strength.is_finite(),
+                PostEffect::ChromaticAberration { strength } => strength.abs() < 1.0,
+                PostEffect::FilmGrain { amount } => amount.is_finite(),
+                PostEffect::Sharpen { amount, radius } => {
+                    amount.is_finite() && radius >= 0.0 && radius.is_finite()
+                }
+            };
+            if !valid {
+                return Err(Error::Invalid(format!(
+                    "The parameters of {:?} are out of range.",
+                    effect
+                )));
+            }
+        }
+        for light in &self.lights {
+            let (color, intensity, placed) = match *light {
+                Light::Directional(ref d) => {
+                    let length = d.direction.length_squared();
+                    (d.color, d.intensity, length.is_finite() && length > 0.0)
+                }
+                Light::Spherical(ref s) => (s.color, s.intensity, finite(&s.position)),
+            };
+            if !(color.is_finite() && intensity.is_finite()) {
+                return invalid("Light colors and intensities have to be finite.");
+            }
+            if !placed {
+                return invalid("Lights need a finite position or a non-zero direction.");
+            }
+        }
+        Ok(())
+    }
+
+    /// The nearest intersection. Hits at distances that aren't finite, from degenerate rays
+    /// or geometry, are ignored.
+    pub fn trace(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        stats::count(Counter::Trace);
         self.elements
             .iter()
-            .filter_map(|s| s.hit(ray).map(|d| Intersection::new(d, s)))
-            .min_by(|i1, i2| i1.distance.partial_cmp(&i2.distance).unwrap())
+            .filter_map(|s| s.hit(ray).and_then(|d| Intersection::new(d, s)))
+            .min_by(|i1, i2| i1.distance.total_cmp(&i2.distance))
+    }
+
+    /// The medium the ray travels through up to `distance`, with no surface in between:
+    /// that of the innermost element around it, or the scene's own.
+    pub fn medium_along(&self, ray: &Ray, distance: Option<f64>) -> Option<&Medium> {
+        // halfway along, the segment is clear of the surfaces at both of its ends
+        let probe = distance.map_or(0.0, |d| d / 2.0);
+        self.elements
+            .iter()
+            .filter_map(|e| e.material().medium.as_ref().map(|m| (e, m)))
+            .filter_map(|(e, m)| {
+                e.intervals(ray)
+                    .into_iter()
+                    .find(|i| i.enter <= probe && probe <= i.exit)
+                    .map(|i| (i.enter, m))
+            })
+            // the innermost one was entered last
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, m)| m)
+            .or(self.medium.as_ref())
+    }
+
+    /// The fraction of light getting along the first `distance` of the ray. Opaque surfaces
+    /// block it, media on the way attenuate it. After `max_recursion` boundaries, it's taken
+    /// to be blocked.
+    pub fn transmittance(&self, ray: &Ray, distance: f64, sample: &CameraSample) -> Color {
+        let mut transmittance = WHITE;
+        let mut ray = Ray {
+            origin: ray.origin,
+            direction: ray.direction,
+            cone: RayCone::default(),
+            time: ray.time,
+        };
+        let mut remaining = distance;
+        for _ in 0..self.max_recursion {
+            let intersection = self.trace(&ray).filter(|i| i.distance < remaining);
+            let segment = intersection.as_ref().map_or(remaining, |i| i.distance);
+            if let Some(medium) = self.medium_along(&ray, Some(segment)) {
+                transmittance =
+                    transmittance * medium.transmittance(&ray, segment, &mut sample.stream());
+            }
+            match intersection {
+                Some(i) => match i.element.material().surface {
+                    SurfaceType::Boundary => {
+                        ray.origin = ray.at(i.distance + self.shadow_bias);
+                        remaining -= i.distance + self.shadow_bias;
+                    }
+                    _ => return BLACK,
+                },
+                None => return transmittance,
+            }
+        }
+        stats::count(Counter::RecursionLimit);
+        BLACK
     }
 }
 
-pub fn get_color(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: u32) -> Color {
+pub fn get_color(
+    scene: &Scene,
+    ray: &Ray,
+    intersection: &Intersection,
+    depth: u32,
+    sample: &CameraSample,
+) -> Color {
     let hit = ray.origin + (ray.direction * intersection.distance);
-    let normal = intersection.element.surface_normal(&hit);
+    let normal = intersection.element.shading_normal(&hit, ray.time);
+    let cone = ray.cone.at(intersection.distance);
+    let footprint = intersection.element.footprint(
+        &hit,
+        ray.time,
+        intersection.element.surface_normal(&hit, ray.time),
+        ray.direction,
+        cone.width,
+    );
 
     let material = intersection.element.material();
     match material.surface {
-        SurfaceType::Diffuse => shade_diffuse(scene, intersection.element, hit, normal),
-        SurfaceType::Reflective { reflectivity } => {
-            let mut color = shade_diffuse(scene, intersection.element, hit, normal);
-            let reflection_ray =
-                Ray::create_reflection(normal, ray.direction, hit, scene.shadow_bias);
+        SurfaceType::Diffuse => shade_diffuse(
+            scene,
+            intersection.element,
+            hit,
+            normal,
+            &footprint,
+            ray.time,
+            sample,
+        ),
+        SurfaceType::Reflective {
+            reflectivity,
+            ref roughness,
+        } => {
+            let mut color = shade_diffuse(
+                scene,
+                intersection.element,
+                hit,
+                normal,
+                &footprint,
+                ray.time,
+                sample,
+            );
+            let reflection_ray = Ray::create_glossy_reflection(
+                normal,
+                ray.direction,
+                hit,
+                scene.shadow_bias,
+                intersection.element.scalar(roughness, &hit, ray.time) as f64,
+                sample.get_2d(depth, Dimension::Bsdf),
+                ray.time,
+            )
+            .with_cone(cone);
+            stats::count(Counter::ReflectionRay);
             // let scattered_ray = Ray::create_scatter(&hit);
             color = color * (1.0 - reflectivity);
-            color = color + (cast_ray(scene, &reflection_ray, depth + 1) * reflectivity);
+            color = color + (cast_ray(scene, &reflection_ray, depth + 1, sample) * reflectivity);
             // + (cast_ray(scene, &scattered_ray, depth + 1) * 0.5);
             color
         }
@@ -56,35 +354,54 @@ pub fn get_color(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: u
             let mut refraction_color = BLACK;
             // the effective reflectivity
             // TODO: Schlick's approximation might be good enough
-            let kr = fresnel(ray.direction, normal, index) as f32;
-            let surface_color = material
-                .color
-                .color(&intersection.element.texture_coordinates(&hit));
+            let mut kr = fresnel(ray.direction, normal, index) as f32;
+            let surface_color = intersection.element.color(&hit, ray.time, &footprint);
 
             if kr < 1.0 {
-                let transmission_ray =
-                    Ray::create_transmission(normal, ray.direction, hit, scene.shadow_bias, index)
-                        .unwrap();
-                refraction_color = cast_ray(scene, &transmission_ray, depth + 1);
+                match Ray::create_transmission(
+                    normal,
+                    ray.direction,
+                    hit,
+                    scene.shadow_bias,
+                    index,
+                    ray.time,
+                ) {
+                    Some(transmission_ray) => {
+                        let transmission_ray = transmission_ray.with_cone(cone);
+                        stats::count(Counter::RefractionRay);
+                        refraction_color = cast_ray(scene, &transmission_ray, depth + 1, sample);
+                    }
+                    // total internal reflection after all, at the critical angle
+                    None => kr = 1.0,
+                }
             }
 
             let reflection_ray =
-                Ray::create_reflection(normal, ray.direction, hit, scene.shadow_bias);
-            let reflection_color = cast_ray(scene, &reflection_ray, depth + 1);
+                Ray::create_reflection(normal, ray.direction, hit, scene.shadow_bias, ray.time)
+                    .with_cone(cone);
+            stats::count(Counter::ReflectionRay);
+            let reflection_color = cast_ray(scene, &reflection_ray, depth + 1, sample);
             let mut color = reflection_color * kr + refraction_color * (1.0 - kr);
             color = color * transparency * surface_color;
             color
         }
+        SurfaceType::Boundary => {
+            let through = ray.pass_through(&hit, scene.shadow_bias).with_cone(cone);
+            cast_ray(scene, &through, depth + 1, sample)
+        }
     }
 }
 
-fn shade_diffuse(
+pub(crate) fn shade_diffuse(
     scene: &Scene,
     element: &Element,
     hit_point: Point,
     surface_normal: Vector3,
+    footprint: &Footprint,
+    time: f64,
+    sample: &CameraSample,
 ) -> Color {
-    let texture_coords = element.texture_coordinates(&hit_point);
+    let surface_color = element.color(&hit_point, time, footprint);
     let mut color = BLACK;
     for light in &scene.lights {
         let direction_to_light = -light.direction_from(&hit_point);
@@ -111,31 +428,28 @@ fn shade_diffuse(
         let shadow_ray = Ray {
             origin: hit_point + (surface_normal * scene.shadow_bias),
             direction: direction_to_light,
+            cone: RayCone::default(),
+            time,
         };
+        stats::count(Counter::ShadowRay);
 
-        let shadow_intersection = scene.trace(&shadow_ray);
-        let in_light = shadow_intersection.is_none()
-            || shadow_intersection.unwrap().distance > light.distance(&hit_point);
+        let visibility = scene.transmittance(&shadow_ray, light.distance(&hit_point), sample);
 
-        let light_intensity = if in_light {
-            light.intensity(&hit_point)
-        } else {
-            0.0
-        };
+        let light_intensity = light.intensity(&hit_point);
         let material = element.material();
         let light_power =
             (surface_normal.dot(&direction_to_light) as f32).max(0.0) * light_intensity;
         let light_reflected = material.albedo / std::f32::consts::PI;
 
-        let light_color = light.color() * light_power * light_reflected;
-        color = color + (material.color.color(&texture_coords) * light_color);
+        let light_color = light.color() * visibility * light_power * light_reflected;
+        color = color + (surface_color * light_color);
     }
     color.clamp()
 }
 
 // TODO: we assumed that the first media has refractive index = 1,
 // which is not always the case
-fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f64 {
+pub(crate) fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f64 {
     let i_dot_n = incident.dot(&normal);
     let mut eta_i = 1.0;
     let mut eta_t = index as f64;
@@ -147,23 +461,153 @@ fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f64 {
     let sin_t = eta_i / eta_t * (1.0 - i_dot_n * i_dot_n).max(0.0).sqrt();
     if sin_t > 1.0 {
         //Total internal reflection
-        return 1.0;
+        1.0
     } else {
         let cos_t = (1.0 - sin_t * sin_t).max(0.0).sqrt();
         let cos_i = cos_t.abs();
         let r_s = ((eta_t * cos_i) - (eta_i * cos_t)) / ((eta_t * cos_i) + (eta_i * cos_t));
         let r_p = ((eta_i * cos_i) - (eta_t * cos_t)) / ((eta_i * cos_i) + (eta_t * cos_t));
-        return (r_s * r_s + r_p * r_p) / 2.0;
+        (r_s * r_s + r_p * r_p) / 2.0
     }
 }
 
-pub fn cast_ray(scene: &Scene, ray: &Ray, depth: u32) -> Color {
+pub fn cast_ray(scene: &Scene, ray: &Ray, depth: u32, sample: &CameraSample) -> Color {
     if depth >= scene.max_recursion {
+        stats::count(Counter::RecursionLimit);
         return BLACK;
     }
+    stats::reach(depth);
 
-    let intersection = scene.trace(&ray);
-    intersection
-        .map(|i| get_color(scene, &ray, &i, depth))
-        .unwrap_or(BLACK)
+    if scene.integrator == Integrator::PathTracing {
+        return trace_path(scene, ray, depth, sample);
+    }
+
+    let intersection = scene.trace(ray);
+    let distance = intersection.as_ref().map(|i| i.distance);
+    let color = intersection
+        .map(|i| get_color(scene, ray, &i, depth, sample))
+        .unwrap_or(BLACK);
+    match scene.medium_along(ray, distance) {
+        Some(medium) => {
+            let limit = distance.unwrap_or(f64::INFINITY);
+            color * medium.transmittance(ray, limit, &mut sample.stream())
+                + single_scattering(scene, medium, ray, limit, sample)
+        }
+        None => color,
+    }
+}
+
+/// The light scattered towards the ray origin by the medium along the first `limit` of the ray,
+/// coming straight from the lights, and the light the medium gives off.
+/// Why the element can't be rendered, if it can't: a radius or size that isn't finite and
+/// positive, or a moving scale that is zero at some point of the motion.
+fn degenerate(element: &Element) -> Option<&'static str> {
+    let positive = |x: f64| x > 0.0 && x.is_finite();
+    match *element {
+        Element::Sphere(ref s) if !positive(s.radius) => {
+            Some("Sphere radii have to be finite and positive.")
+        }
+        Element::Box(ref b)
+            if !(positive(b.max.x - b.min.x)
+                && positive(b.max.y - b.min.y)
+                && positive(b.max.z - b.min.z)) =>
+        {
+            Some("Boxes need a finite, non-zero size along every axis.")
+        }
+        Element::Cylinder(ref c) if !positive(c.radius) => {
+            Some("Cylinder radii have to be finite and positive.")
+        }
+        Element::Cone(ref c) if !positive(c.radius) => {
+            Some("Cone radii have to be finite and positive.")
+        }
+        Element::Torus(ref t) if !(positive(t.major_radius) && positive(t.minor_radius)) => {
+            Some("Torus radii have to be finite and positive.")
+        }
+        Element::Instance(ref i) => degenerate(&i.element),
+        Element::Csg(ref c) if !(solid(&c.left) && solid(&c.right)) => {
+            Some("CSG operands have to be solids.")
+        }
+        Element::Csg(ref c) => degenerate(&c.left).or_else(|| degenerate(&c.right)),
+        Element::Moving(ref m) => {
+            let scales: Vec<[f64; 3]> = m
+                .motion
+                .keyframes()
+                .iter()
+                .map(|k| [k.value.scale.x, k.value.scale.y, k.value.scale.z])
+                .collect();
+            let invertible = scales
+                .iter()
+                .all(|s| s.iter().all(|&f| f != 0.0 && f.is_finite()));
+            // interpolating between factors of opposite sign passes through zero
+            let steady = scales
+                .windows(2)
+                .all(|w| (0..3).all(|i| w[0][i].signum() == w[1][i].signum()));
+            if invertible && steady {
+                degenerate(&m.element)
+            } else {
+                Some("Moving scales have to be finite and non-zero over the whole motion.")
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Whether the element has an inside, see `Hittable::intervals`. Disks, rectangles and
+/// heightfields are only surfaces.
+fn solid(element: &Element) -> bool {
+    match *element {
+        Element::Disk(_) | Element::Rectangle(_) | Element::Heightfield(_) => false,
+        Element::Instance(ref i) => solid(&i.element),
+        Element::Moving(ref m) => solid(&m.element),
+        _ => true,
+    }
+}
+
+fn single_scattering(
+    scene: &Scene,
+    medium: &Medium,
+    ray: &Ray,
+    limit: f64,
+    sample: &CameraSample,
+) -> Color {
+    let mut rng = sample.stream();
+    let mut color = BLACK;
+    for _ in 0..SCATTERING_SAMPLES {
+        if let Flight::Scattered {
+            distance,
+            weight,
+            emission,
+        } = medium.sample_scattering(ray, limit, &mut rng)
+        {
+            let point = ray.at(distance);
+            let scattered = weight * in_scattered_light(scene, medium, &point, ray, sample);
+            color = color + (scattered + emission) * (1.0 / SCATTERING_SAMPLES as f32);
+        }
+    }
+    color
+}
+
+/// The light from the lights scattered into `direction` at a point of the medium.
+pub(crate) fn in_scattered_light(
+    scene: &Scene,
+    medium: &Medium,
+    point: &Point,
+    ray: &Ray,
+    sample: &CameraSample,
+) -> Color {
+    let mut color = BLACK;
+    for light in &scene.lights {
+        let direction_to_light = -light.direction_from(point);
+        let shadow_ray = Ray {
+            origin: *point,
+            direction: direction_to_light,
+            cone: RayCone::default(),
+            time: ray.time,
+        };
+        stats::count(Counter::ShadowRay);
+        let visibility = scene.transmittance(&shadow_ray, light.distance(point), sample);
+        let phase = medium.phase(direction_to_light.dot(&ray.direction)) as f32;
+        color = color + light.color() * visibility * (light.intensity(point) * phase);
+    }
+    color
 }
@@ -0,0 +1,352 @@
+use crate::point::Point;
+use crate::primitives::{Hittable, Interval, Material, TextureCoordinates};
+use crate::ray::Ray;
+use crate::stats::{self, Counter};
+use crate::vector3::Vector3;
+
+/// A signed distance function: negative inside the shape, positive outside.
+#[derive(Debug)]
+pub enum Sdf {
+    Sphere {
+        center: Point,
+        radius: f64,
+    },
+    Box {
+        center: Point,
+        half_extents: Vector3,
+    },
+    /// A torus lying in the xz plane.
+    Torus {
+        center: Point,
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    Capsule {
+        a: Point,
+        b: Point,
+        radius: f64,
+    },
+    /// The half-space below the plane through `point`, `normal` pointing outwards.
+    Plane {
+        point: Point,
+        normal: Vector3,
+    },
+    /// The power-`power` Mandelbulb fractal around the origin.
+    Mandelbulb {
+        power: f64,
+        iterations: u32,
+    },
+    Union(Box<Sdf>, Box<Sdf>),
+    Intersection(Box<Sdf>, Box<Sdf>),
+    /// The left shape with the right one cut out of it.
+    Difference(Box<Sdf>, Box<Sdf>),
+    /// A union blending the shapes over `smoothness` distance.
+    SmoothUnion {
+        left: Box<Sdf>,
+        right: Box<Sdf>,
+        smoothness: f64,
+    },
+    SmoothIntersection {
+        left: Box<Sdf>,
+        right: Box<Sdf>,
+        smoothness: f64,
+    },
+    SmoothDifference {
+        left: Box<Sdf>,
+        right: Box<Sdf>,
+        smoothness: f64,
+    },
+    Translate {
+        inner: Box<Sdf>,
+        offset: Vector3,
+    },
+    Scale {
+        inner: Box<Sdf>,
+        factor: f64,
+    },
+    /// Rotation around the y axis by `amount` radians per unit of height.
+    Twist {
+        inner: Box<Sdf>,
+        amount: f64,
+    },
+    /// Infinite copies of the shape, one in each cell of size `period`.
+    /// Zero components don't repeat.
+    Repeat {
+        inner: Box<Sdf>,
+        period: Vector3,
+    },
+    /// Grow the shape by `radius`, rounding its edges.
+    Round {
+        inner: Box<Sdf>,
+        radius: f64,
+    },
+}
+
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b + (a - b) * h - k * h * (1.0 - h)
+}
+
+fn repeat(value: f64, period: f64) -> f64 {
+    if period == 0.0 {
+        value
+    } else {
+        value - period * (value / period).round()
+    }
+}
+
+impl Sdf {
+    pub fn distance(&self, p: &Point) -> f64 {
+        match *self {
+            Sdf::Sphere { center, radius } => (*p - center).length() - radius,
+            Sdf::Box {
+                center,
+                half_extents,
+            } => {
+                let d = *p - center;
+                let q = Vector3 {
+                    x: d.x.abs() - half_extents.x,
+                    y: d.y.abs() - half_extents.y,
+                    z: d.z.abs() - half_extents.z,
+                };
+                let outside = Vector3 {
+                    x: q.x.max(0.0),
+                    y: q.y.max(0.0),
+                    z: q.z.max(0.0),
+                };
+                outside.length() + q.x.max(q.y.max(q.z)).min(0.0)
+            }
+            Sdf::Torus {
+                center,
+                major_radius,
+                minor_radius,
+            } => {
+                let d = *p - center;
+                let radial = (d.x * d.x + d.z * d.z).sqrt() - major_radius;
+                (radial * radial + d.y * d.y).sqrt() - minor_radius
+            }
+            Sdf::Capsule { a, b, radius } => {
+                let pa = *p - a;
+                let ba = b - a;
+                let h = (pa.dot(&ba) / ba.dot(&ba)).clamp(0.0, 1.0);
+                (pa - ba * h).length() - radius
+            }
+            Sdf::Plane { point, normal } => (*p - point).dot(&normal.normalize()),
+            Sdf::Mandelbulb { power, iterations } => mandelbulb(p, power, iterations),
+            Sdf::Union(ref a, ref b) => a.distance(p).min(b.distance(p)),
+            Sdf::Intersection(ref a, ref b) => a.distance(p).max(b.distance(p)),
+            Sdf::Difference(ref a, ref b) => a.distance(p).max(-b.distance(p)),
+            Sdf::SmoothUnion {
+                ref left,
+                ref right,
+                smoothness,
+            } => smooth_min(left.distance(p), right.distance(p), smoothness),
+            Sdf::SmoothIntersection {
+                ref left,
+                ref right,
+                smoothness,
+            } => -smooth_min(-left.distance(p), -right.distance(p), smoothness),
+            Sdf::SmoothDifference {
+                ref left,
+                ref right,
+                smoothness,
+            } => -smooth_min(-left.distance(p), right.distance(p), smoothness),
+            Sdf::Translate { ref inner, offset } => inner.distance(&(*p + -offset)),
+            Sdf::Scale { ref inner, factor } => inner.distance(&(*p / factor)) * factor,
+            Sdf::Twist { ref inner, amount } => {
+                let (sin, cos) = (amount * p.y).sin_cos();
+                let twisted = Point {
+                    x: cos * p.x - sin * p.z,
+                    y: p.y,
+                    z: sin * p.x + cos * p.z,
+                };
+                // twisting stretches space, so the distance has to be scaled down
+                // to stay a lower bound
+                let radial = (p.x * p.x + p.z * p.z).sqrt();
+                inner.distance(&twisted) / (1.0 + (amount * radial).powi(2)).sqrt()
+            }
+            Sdf::Repeat { ref inner, period } => inner.distance(&Point {
+                x: repeat(p.x, period.x),
+                y: repeat(p.y, period.y),
+                z: repeat(p.z, period.z),
+            }),
+            Sdf::Round { ref inner, radius } => inner.distance(p) - radius,
+        }
+    }
+}
+
+// Radius of a sphere enclosing the Mandelbulb
+const MANDELBULB_BOUND: f64 = 1.2;
+
+/// The distance estimator of the Mandelbulb, from the running derivative of the iteration.
+fn mandelbulb(p: &Point, power: f64, iterations: u32) -> f64 {
+    let c = Vector3 {
+        x: p.x,
+        y: p.y,
+        z: p.z,
+    };
+    // the estimator overshoots far away, where the bounding sphere is a safe bound
+    if c.length() > MANDELBULB_BOUND + 0.3 {
+        return c.length() - MANDELBULB_BOUND;
+    }
+    let mut z = c;
+    let mut derivative = 1.0;
+    let mut r = z.length();
+    for _ in 0..iterations {
+        r = z.length();
+        if r > 2.0 {
+            break;
+        }
+        // stuck at the origin, inside the set, with no angles to take
+        if r == 0.0 {
+            return 0.0;
+        }
+        let theta = (z.z / r).acos() * power;
+        let phi = z.y.atan2(z.x) * power;
+        derivative = r.powf(power - 1.0) * power * derivative + 1.0;
+        let zr = r.powf(power);
+        z = Vector3 {
+            x: theta.sin() * phi.cos(),
+            y: phi.sin() * theta.sin(),
+            z: theta.cos(),
+        } * zr
+            + c;
+    }
+    0.5 * r.ln() * r / derivative
+}
+
+/// A shape defined by a signed distance function, rendered by sphere tracing.
+#[derive(Debug)]
+pub struct DistanceField {
+    pub sdf: Sdf,
+    pub material: Material,
+    /// The surface is hit once the distance drops below this.
+    pub epsilon: f64,
+    pub max_steps: u32,
+    pub max_distance: f64,
+}
+
+impl DistanceField {
+    /// Step from `t` until the ray is at least `epsilon` away from the surface, so that rays
+    /// leaving the surface (shadow, reflection and refraction rays) don't hit it right away.
+    fn escape(&self, ray: &Ray, mut t: f64, steps: &mut u32) -> f64 {
+        while self.sdf.distance(&ray.at(t)).abs() < self.epsilon && *steps < self.max_steps {
+            t += 2.0 * self.epsilon;
+            *steps += 1;
+        }
+        t
+    }
+
+    /// March until the next surface crossing after `t`. The absolute distance is used so that
+    /// rays travelling inside the shape can be traced, too.
+    fn march(&self, ray: &Ray, mut t: f64, steps: &mut u32) -> Option<f64> {
+        while *steps < self.max_steps {
+            let distance = self.sdf.distance(&ray.at(t)).abs();
+            if distance < self.epsilon {
+                return Some(t);
+            }
+            t += distance;
+            if t > self.max_distance {
+                return None;
+            }
+            *steps += 1;
+        }
+        None
+    }
+}
+
+impl Hittable for DistanceField {
+    fn hit(&self, ray: &Ray) -> Option<f64> {
+        let mut steps = 0;
+        let start = self.escape(ray, 0.0, &mut steps);
+        let hit = self.march(ray, start, &mut steps);
+        stats::add(Counter::MarchStep, steps as u64);
+        hit
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: f64) -> Vector3 {
+        // central differences of the distance approximate its gradient
+        let h = self.epsilon;
+        let d = |x: f64, y: f64, z: f64| {
+            self.sdf.distance(&Point {
+                x: hit_point.x + x,
+                y: hit_point.y + y,
+                z: hit_point.z + z,
+            })
+        };
+        Vector3 {
+            x: d(h, 0.0, 0.0) - d(-h, 0.0, 0.0),
+            y: d(0.0, h, 0.0) - d(0.0, -h, 0.0),
+            z: d(0.0, 0.0, h) - d(0.0, 0.0, -h),
+        }
+        .normalize()
+    }
+
+    /// Projected along the dominant axis of the normal, as there's no parametrization.
+    fn texture_coordinates(&self, hit_point: &Point, time: f64) -> TextureCoordinates {
+        let n = self.surface_normal(hit_point, time);
+        let (x, y) = if n.x.abs() >= n.y.abs() && n.x.abs() >= n.z.abs() {
+            (hit_point.z, hit_point.y)
+        } else if n.y.abs() >= n.z.abs() {
+            (hit_point.x, hit_point.z)
+        } else {
+            (hit_point.x, hit_point.y)
+        };
+        TextureCoordinates {
+            x: x as f32,
+            y: y as f32,
+        }
+    }
+
+    fn tangent(&self, hit_point: &Point, time: f64) -> Vector3 {
+        let n = self.surface_normal(hit_point, time);
+        if n.x.abs() >= n.y.abs() && n.x.abs() >= n.z.abs() {
+            Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            }
+        } else {
+            Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            }
+        }
+    }
+
+    /// Only the part of the ray in front of its origin is traced; a ray starting inside
+    /// the shape enters it at negative infinity.
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let mut intervals = Vec::new();
+        let mut steps = 0;
+        let mut t = 0.0;
+        let mut enter = if self.sdf.distance(&ray.origin) < 0.0 {
+            Some(f64::NEG_INFINITY)
+        } else {
+            None
+        };
+        t = self.escape(ray, t, &mut steps);
+        while let Some(crossing) = self.march(ray, t, &mut steps) {
+            match enter.take() {
+                Some(start) => intervals.push(Interval {
+                    enter: start,
+                    exit: crossing,
+                }),
+                None => enter = Some(crossing),
+            }
+            t = self.escape(ray, crossing, &mut steps);
+        }
+        if let Some(start) = enter {
+            intervals.push(Interval {
+                enter: start,
+                exit: f64::INFINITY,
+            });
+        }
+        stats::add(Counter::MarchStep, steps as u64);
+        intervals
+    }
+}
@@ -0,0 +1,254 @@
+use crate::primitives::Element;
+use std::cell::Cell;
+use std::fmt;
+use std::ops::AddAssign;
+use std::time::Duration;
+
+/// The kinds of elements, in the order of `RenderStats::intersection_tests`.
+pub const ELEMENT_KINDS: [&str; 13] = [
+    "sphere",
+    "plane",
+    "box",
+    "disk",
+    "rectangle",
+    "cylinder",
+    "cone",
+    "torus",
+    "instance",
+    "csg",
+    "sdf",
+    "heightfield",
+    "moving",
+];
+
+/// What happened during a render, for finding out what makes a scene slow.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    pub camera_rays: u64,
+    pub shadow_rays: u64,
+    pub reflection_rays: u64,
+    pub refraction_rays: u64,
+    /// Diffuse bounces and scattering in media, followed by the path tracer.
+    pub scattered_rays: u64,
+    /// Ray tests by the kind of element, see `ELEMENT_KINDS`. Every ray traced through the
+    /// scene is tested against every element, as there's no acceleration structure over the
+    /// elements, and wrapping elements like instances and CSG count along with what they wrap.
+    /// The probes of CSG shading and of media aren't traced, so they don't count.
+    pub intersection_tests: [u64; ELEMENT_KINDS.len()],
+    /// Blocks of the heightfield range pyramids a ray was tested against, the acceleration
+    /// structure within a heightfield, counting the cells at the bottom.
+    pub heightfield_nodes: u64,
+    /// Steps of marching along rays through distance fields. Unlike the intersection tests,
+    /// these include the probes of CSG shading and of media.
+    pub march_steps: u64,
+    /// Rays not followed because they were `Scene::max_recursion` deep.
+    pub recursion_limit_hits: u64,
+    /// Camera rays whose light came out NaN or infinite, from degenerate geometry, and which
+    /// were counted as black.
+    pub invalid_samples: u64,
+    /// The depth of the deepest ray of each camera ray, summed, see `average_path_depth`.
+    pub path_depths: u64,
+    /// Taking the samples.
+    pub sampling_time: Duration,
+    /// Weighting the samples into the pixels by the reconstruction filter.
+    pub splatting_time: Duration,
+    /// Applying `Scene::post` and encoding the image.
+    pub post_processing_time: Duration,
+}
+
+impl RenderStats {
+    pub fn rays(&self) -> u64 {
+        self.camera_rays
+            + self.shadow_rays
+            + self.reflection_rays
+            + self.refraction_rays
+            + self.scattered_rays
+    }
+
+    pub fn average_path_depth(&self) -> f64 {
+        self.path_depths as f64 / self.camera_rays.max(1) as f64
+    }
+
+    pub fn total_time(&self) -> Duration {
+        self.sampling_time + self.splatting_time + self.post_processing_time
+    }
+}
+
+impl AddAssign for RenderStats {
+    fn add_assign(&mut self, other: RenderStats) {
+        self.camera_rays += other.camera_rays;
+        self.shadow_rays += other.shadow_rays;
+        self.reflection_rays += other.reflection_rays;
+        self.refraction_rays += other.refraction_rays;
+        self.scattered_rays += other.scattered_rays;
+        for (tests, other) in self
+            .intersection_tests
+            .iter_mut()
+            .zip(other.intersection_tests.iter())
+        {
+            *tests += other;
+        }
+        self.heightfield_nodes += other.heightfield_nodes;
+        self.march_steps += other.march_steps;
+        self.recursion_limit_hits += other.recursion_limit_hits;
+        self.invalid_samples += other.invalid_samples;
+        self.path_depths += other.path_depths;
+        self.sampling_time += other.sampling_time;
+        self.splatting_time += other.splatting_time;
+        self.post_processing_time += other.post_processing_time;
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.sampling_time.as_secs_f64().max(1e-9);
+        writeln!(
+            f,
+            "rays: {} ({:.2}M/s while sampling)",
+            self.rays(),
+            self.rays() as f64 / seconds / 1e6
+        )?;
+        writeln!(f, "  camera: {}", self.camera_rays)?;
+        writeln!(f, "  shadow: {}", self.shadow_rays)?;
+        writeln!(f, "  reflection: {}", self.reflection_rays)?;
+        writeln!(f, "  refraction: {}", self.refraction_rays)?;
+        writeln!(f, "  scattered: {}", self.scattered_rays)?;
+        writeln!(
+            f,
+            "intersection tests: {}",
+            self.intersection_tests.iter().sum::<u64>()
+        )?;
+        for (kind, tests) in ELEMENT_KINDS.iter().zip(self.intersection_tests.iter()) {
+            if *tests > 0 {
+                writeln!(f, "  {}: {}", kind, tests)?;
+            }
+        }
+        if self.heightfield_nodes > 0 {
+            writeln!(f, "heightfield nodes: {}", self.heightfield_nodes)?;
+        }
+        if self.march_steps > 0 {
+            writeln!(f, "march steps: {}", self.march_steps)?;
+        }
+        writeln!(f, "average path depth: {:.2}", self.average_path_depth())?;
+        writeln!(f, "recursion limit hits: {}", self.recursion_limit_hits)?;
+        writeln!(f, "invalid samples: {}", self.invalid_samples)?;
+        writeln!(f, "sampling: {:?}", self.sampling_time)?;
+        writeln!(f, "splatting: {:?}", self.splatting_time)?;
+        write!(f, "post-processing: {:?}", self.post_processing_time)
+    }
+}
+
+/// What is counted while tracing.
+#[derive(Clone, Copy)]
+pub(crate) enum Counter {
+    CameraRay,
+    ShadowRay,
+    ReflectionRay,
+    RefractionRay,
+    ScatteredRay,
+    RecursionLimit,
+    InvalidSample,
+    /// A ray traced through the scene, see `tests_per_trace`.
+    Trace,
+    HeightfieldNode,
+    MarchStep,
+}
+
+const COUNTERS: usize = 10;
+
+thread_local! {
+    static COUNTS: [Cell<u64>; COUNTERS] = const { [const { Cell::new(0) }; COUNTERS] };
+    static DEPTH: Cell<(u32, u64)> = const { Cell::new((0, 0)) };
+}
+
+pub(crate) fn count(counter: Counter) {
+    add(counter, 1);
+}
+
+/// Count `n` at once, for loops counting locally.
+pub(crate) fn add(counter: Counter, n: u64) {
+    COUNTS.with(|counts| {
+        let count = &counts[counter as usize];
+        count.set(count.get() + n)
+    });
+}
+
+/// The intersection tests of tracing a ray through the `elements`, by kind. They are the same
+/// for every ray, so they are worked out once per pass rather than counted on every test.
+pub(crate) fn tests_per_trace(elements: &[Element]) -> [u64; ELEMENT_KINDS.len()] {
+    fn add(element: &Element, tests: &mut [u64; ELEMENT_KINDS.len()]) {
+        tests[kind(element)] += 1;
+        match *element {
+            Element::Instance(ref i) => add(&i.element, tests),
+            Element::Csg(ref c) => {
+                add(&c.left, tests);
+                add(&c.right, tests);
+            }
+            Element::Moving(ref m) => add(&m.element, tests),
+            _ => {}
+        }
+    }
+    let mut tests = [0; ELEMENT_KINDS.len()];
+    for element in elements {
+        add(element, &mut tests);
+    }
+    tests
+}
+
+fn kind(element: &Element) -> usize {
+    match *element {
+        Element::Sphere(_) => 0,
+        Element::Plane(_) => 1,
+        Element::Box(_) => 2,
+        Element::Disk(_) => 3,
+        Element::Rectangle(_) => 4,
+        Element::Cylinder(_) => 5,
+        Element::Cone(_) => 6,
+        Element::Torus(_) => 7,
+        Element::Instance(_) => 8,
+        Element::Csg(_) => 9,
+        Element::Sdf(_) => 10,
+        Element::Heightfield(_) => 11,
+        Element::Moving(_) => 12,
+    }
+}
+
+/// Record that the path of the current camera ray got `depth` deep.
+pub(crate) fn reach(depth: u32) {
+    DEPTH.with(|d| {
+        let (deepest, total) = d.get();
+        d.set((deepest.max(depth), total));
+    });
+}
+
+/// Done with the path of the current camera ray.
+pub(crate) fn end_path() {
+    DEPTH.with(|d| {
+        let (deepest, total) = d.get();
+        d.set((0, total + deepest as u64));
+    });
+}
+
+/// The counts of this thread since the last call, which start again from zero. Each traced
+/// ray made the `tests_per_trace`.
+pub(crate) fn take(tests_per_trace: &[u64; ELEMENT_KINDS.len()]) -> RenderStats {
+    let mut stats = RenderStats::default();
+    COUNTS.with(|counts| {
+        let take = |counter: Counter| counts[counter as usize].replace(0);
+        stats.camera_rays = take(Counter::CameraRay);
+        stats.shadow_rays = take(Counter::ShadowRay);
+        stats.reflection_rays = take(Counter::ReflectionRay);
+        stats.refraction_rays = take(Counter::RefractionRay);
+        stats.scattered_rays = take(Counter::ScatteredRay);
+        stats.recursion_limit_hits = take(Counter::RecursionLimit);
+        stats.invalid_samples = take(Counter::InvalidSample);
+        stats.heightfield_nodes = take(Counter::HeightfieldNode);
+        stats.march_steps = take(Counter::MarchStep);
+        let traces = take(Counter::Trace);
+        for (total, tests) in stats.intersection_tests.iter_mut().zip(tests_per_trace) {
+            *total = traces * tests;
+        }
+    });
+    stats.path_depths = DEPTH.with(|d| d.replace((0, 0)).1);
+    stats
+}
@@ -0,0 +1,676 @@
+use crate::color::Color;
+use crate::point::Point;
+use crate::primitives::{plane_axes, TextureCoordinates};
+use crate::utils::{self, wrap};
+use crate::vector3::Vector3;
+use std::fmt;
+use std::ops::{Add, Mul};
+
+use image::{DynamicImage, GenericImageView};
+
+#[derive(Clone, Copy, Debug)]
+pub enum TextureSpace {
+    /// Evaluate the pattern in the surface texture coordinates.
+    Uv,
+    /// Evaluate the pattern at the world-space hit point.
+    World,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Pattern {
+    Checkerboard,
+    Stripes,
+    Gradient,
+    Noise,
+    Fbm { octaves: u32 },
+    Turbulence { octaves: u32 },
+    Marble { octaves: u32, turbulence: f64 },
+    Wood { rings: f64, turbulence: f64 },
+    Voronoi,
+}
+
+/// A texture computed on the fly instead of being looked up in an image.
+#[derive(Clone, Copy, Debug)]
+pub struct Procedural {
+    pub pattern: Pattern,
+    pub space: TextureSpace,
+    pub scale: f64,
+    pub primary: Color,
+    pub secondary: Color,
+}
+
+impl Procedural {
+    /// The raw pattern value in the `0..=1` range.
+    pub fn value(&self, texture_coords: &TextureCoordinates, hit_point: &Point) -> f32 {
+        let p = match self.space {
+            TextureSpace::Uv => Point {
+                x: texture_coords.x as f64 * self.scale,
+                y: texture_coords.y as f64 * self.scale,
+                z: 0.0,
+            },
+            TextureSpace::World => Point {
+                x: hit_point.x * self.scale,
+                y: hit_point.y * self.scale,
+                z: hit_point.z * self.scale,
+            },
+        };
+
+        let value = match self.pattern {
+            Pattern::Checkerboard => {
+                let sum = p.x.floor() + p.y.floor() + p.z.floor();
+                if sum.rem_euclid(2.0) < 1.0 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+            Pattern::Stripes => {
+                if p.x.rem_euclid(2.0) < 1.0 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+            Pattern::Gradient => p.x.rem_euclid(1.0),
+            Pattern::Noise => 0.5 + 0.5 * perlin(&p),
+            Pattern::Fbm { octaves } => 0.5 + 0.5 * fbm(&p, octaves),
+            Pattern::Turbulence { octaves } => turbulence(&p, octaves),
+            Pattern::Marble {
+                octaves,
+                turbulence: amount,
+            } => 0.5 + 0.5 * (p.x + amount * turbulence(&p, octaves)).sin(),
+            Pattern::Wood {
+                rings,
+                turbulence: amount,
+            } => {
+                let distance = (p.x * p.x + p.y * p.y).sqrt();
+                (distance * rings + amount * perlin(&p)).rem_euclid(1.0)
+            }
+            Pattern::Voronoi => worley(&p),
+        };
+        (value as f32).clamp(0.0, 1.0)
+    }
+
+    pub fn color(&self, texture_coords: &TextureCoordinates, hit_point: &Point) -> Color {
+        let t = self.value(texture_coords, hit_point);
+        self.primary * (1.0 - t) + self.secondary * t
+    }
+}
+
+#[derive(Debug)]
+pub enum Projection {
+    /// The texture coordinates of the primitive itself.
+    Native,
+    /// Projection onto a plane with the given normal.
+    Planar { normal: Vector3 },
+    /// Wrapped around the y axis, with the height as the y coordinate.
+    Cylindrical,
+    /// Latitude and longitude around the mapping origin.
+    Spherical,
+    /// Planar projections along the three axes, blended by the surface normal.
+    Triplanar { sharpness: f64 },
+}
+
+/// Decides how a material places its textures on a surface.
+#[derive(Debug)]
+pub struct TextureMapping {
+    pub projection: Projection,
+    /// The origin of the projections, in world space.
+    pub origin: Point,
+    pub scale: TextureCoordinates,
+    pub offset: TextureCoordinates,
+    /// Rotation of the texture coordinates in radians.
+    pub rotation: f32,
+}
+
+impl Default for TextureMapping {
+    fn default() -> Self {
+        TextureMapping {
+            projection: Projection::Native,
+            origin: Point::zero(),
+            scale: TextureCoordinates { x: 1.0, y: 1.0 },
+            offset: TextureCoordinates { x: 0.0, y: 0.0 },
+            rotation: 0.0,
+        }
+    }
+}
+
+impl TextureMapping {
+    /// Whether the mapping leaves the native texture coordinates untouched.
+    pub fn is_identity(&self) -> bool {
+        matches!(self.projection, Projection::Native)
+            && self.scale.x == 1.0
+            && self.scale.y == 1.0
+            && self.offset.x == 0.0
+            && self.offset.y == 0.0
+            && self.rotation == 0.0
+    }
+
+    /// The mapped texture coordinates. Triplanar mapping uses the dominant axis only.
+    pub fn coordinates(
+        &self,
+        native: &TextureCoordinates,
+        hit_point: &Point,
+        normal: &Vector3,
+    ) -> TextureCoordinates {
+        let p = *hit_point - self.origin;
+        let projected = match self.projection {
+            Projection::Native => *native,
+            Projection::Planar { ref normal } => {
+                let (x_axis, y_axis) = plane_axes(normal);
+                TextureCoordinates {
+                    x: p.dot(&x_axis) as f32,
+                    y: p.dot(&y_axis) as f32,
+                }
+            }
+            Projection::Cylindrical => TextureCoordinates {
+                x: (1.0 + (p.z.atan2(p.x) as f32) / std::f32::consts::PI) * 0.5,
+                y: p.y as f32,
+            },
+            Projection::Spherical => {
+                // The origin itself has no direction, so give it the equator
+                let length = p.length();
+                let height = if length > 0.0 { p.y / length } else { 0.0 };
+                TextureCoordinates {
+                    x: (1.0 + (p.z.atan2(p.x) as f32) / std::f32::consts::PI) * 0.5,
+                    y: height.clamp(-1.0, 1.0).acos() as f32 / std::f32::consts::PI,
+                }
+            }
+            Projection::Triplanar { .. } => {
+                let (ax, ay, az) = (normal.x.abs(), normal.y.abs(), normal.z.abs());
+                if ax >= ay && ax >= az {
+                    triplanar_x(&p)
+                } else if ay >= az {
+                    triplanar_y(&p)
+                } else {
+                    triplanar_z(&p)
+                }
+            }
+        };
+        self.transform(&projected)
+    }
+
+    /// Evaluate `lookup` at the mapped texture coordinates, blending the three
+    /// lookups of triplanar mapping.
+    pub fn blend<T, F>(
+        &self,
+        native: &TextureCoordinates,
+        hit_point: &Point,
+        normal: &Vector3,
+        lookup: F,
+    ) -> T
+    where
+        T: Add<Output = T> + Mul<f32, Output = T>,
+        F: Fn(&TextureCoordinates) -> T,
+    {
+        match self.projection {
+            Projection::Triplanar { sharpness } => {
+                let normal = normal.normalize();
+                let wx = normal.x.abs().powf(sharpness);
+                let wy = normal.y.abs().powf(sharpness);
+                let wz = normal.z.abs().powf(sharpness);
+                let total = wx + wy + wz;
+                let p = *hit_point - self.origin;
+                lookup(&self.transform(&triplanar_x(&p))) * (wx / total) as f32
+                    + lookup(&self.transform(&triplanar_y(&p))) * (wy / total) as f32
+                    + lookup(&self.transform(&triplanar_z(&p))) * (wz / total) as f32
+            }
+            _ => lookup(&self.coordinates(native, hit_point, normal)),
+        }
+    }
+
+    /// Apply the scale, rotation and offset, in that order.
+    fn transform(&self, coords: &TextureCoordinates) -> TextureCoordinates {
+        let x = coords.x * self.scale.x;
+        let y = coords.y * self.scale.y;
+        let (sin, cos) = self.rotation.sin_cos();
+        TextureCoordinates {
+            x: x * cos - y * sin + self.offset.x,
+            y: x * sin + y * cos + self.offset.y,
+        }
+    }
+}
+
+fn triplanar_x(p: &Vector3) -> TextureCoordinates {
+    TextureCoordinates {
+        x: p.z as f32,
+        y: p.y as f32,
+    }
+}
+
+fn triplanar_y(p: &Vector3) -> TextureCoordinates {
+    TextureCoordinates {
+        x: p.x as f32,
+        y: p.z as f32,
+    }
+}
+
+fn triplanar_z(p: &Vector3) -> TextureCoordinates {
+    TextureCoordinates {
+        x: p.x as f32,
+        y: p.y as f32,
+    }
+}
+
+/// A single float material parameter, e.g. roughness or bump height.
+#[derive(Debug)]
+pub enum Scalar {
+    Constant(f32),
+    /// The pattern value remapped to the `min..=max` range.
+    Procedural {
+        source: Procedural,
+        min: f32,
+        max: f32,
+    },
+    /// The luminance of a grayscale image, usually loaded with `ImageTexture::new_linear`.
+    Texture(ImageTexture),
+}
+
+impl Scalar {
+    pub fn value(&self, texture_coords: &TextureCoordinates, hit_point: &Point) -> f32 {
+        match *self {
+            Scalar::Constant(v) => v,
+            Scalar::Procedural {
+                ref source,
+                min,
+                max,
+            } => min + (max - min) * source.value(texture_coords, hit_point),
+            Scalar::Texture(ref tex) => tex.sample(texture_coords, &Footprint::point()).luminance(),
+        }
+    }
+}
+
+fn hash(x: i64, y: i64, z: i64) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^ (h >> 16)
+}
+
+fn gradient(hash: u32, x: f64, y: f64, z: f64) -> f64 {
+    // The 12 cube edge directions of improved Perlin noise
+    match hash % 12 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x + z,
+        5 => -x + z,
+        6 => x - z,
+        7 => -x - z,
+        8 => y + z,
+        9 => -y + z,
+        10 => y - z,
+        _ => -y - z,
+    }
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+/// Gradient noise in the `-1..=1` range.
+pub fn perlin(p: &Point) -> f64 {
+    let (xi, yi, zi) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let (x, y, z) = (p.x - xi, p.y - yi, p.z - zi);
+    let (xi, yi, zi) = (xi as i64, yi as i64, zi as i64);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    let corner = |dx: i64, dy: i64, dz: i64| {
+        gradient(
+            hash(xi + dx, yi + dy, zi + dz),
+            x - dx as f64,
+            y - dy as f64,
+            z - dz as f64,
+        )
+    };
+
+    let value = lerp(
+        w,
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+            lerp(u, corner(0, 1, 0), corner(1, 1, 0)),
+        ),
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+            lerp(u, corner(0, 1, 1), corner(1, 1, 1)),
+        ),
+    );
+    value.clamp(-1.0, 1.0)
+}
+
+/// Fractional Brownian motion: octaves of noise summed with halving amplitude.
+pub fn fbm(p: &Point, octaves: u32) -> f64 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut total = 0.0;
+    let mut frequency = 1.0;
+    for _ in 0..octaves.max(1) {
+        sum += amplitude * perlin(&(*p * frequency));
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / total
+}
+
+/// Like `fbm`, but sums the absolute value of each octave.
+pub fn turbulence(p: &Point, octaves: u32) -> f64 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut total = 0.0;
+    let mut frequency = 1.0;
+    for _ in 0..octaves.max(1) {
+        sum += amplitude * perlin(&(*p * frequency)).abs();
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / total
+}
+
+/// Distance to the closest feature point (Worley F1), one point per unit cell.
+pub fn worley(p: &Point) -> f64 {
+    let (xi, yi, zi) = (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+    let mut closest = f64::INFINITY;
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            for dz in -1..=1 {
+                let (cx, cy, cz) = (xi + dx, yi + dy, zi + dz);
+                let h = hash(cx, cy, cz);
+                let feature = Point {
+                    x: cx as f64 + (h & 0x3ff) as f64 / 1023.0,
+                    y: cy as f64 + ((h >> 10) & 0x3ff) as f64 / 1023.0,
+                    z: cz as f64 + ((h >> 20) & 0x3ff) as f64 / 1023.0,
+                };
+                closest = closest.min((feature - *p).length());
+            }
+        }
+    }
+    closest
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    /// Bilinear lookups blended between the two closest mipmap levels.
+    Trilinear,
+    /// Up to `max_samples` trilinear lookups along the major axis of the footprint.
+    Anisotropic {
+        max_samples: u32,
+    },
+}
+
+/// The texture-space extent of a pixel around a lookup, spanned by two axes.
+#[derive(Clone, Copy)]
+pub struct Footprint {
+    pub dx: TextureCoordinates,
+    pub dy: TextureCoordinates,
+}
+
+impl Footprint {
+    /// An infinitely small footprint, which always samples the full resolution texture.
+    pub fn point() -> Footprint {
+        let zero = TextureCoordinates { x: 0.0, y: 0.0 };
+        Footprint { dx: zero, dy: zero }
+    }
+}
+
+struct MipLevel {
+    width: u32,
+    height: u32,
+    texels: Vec<Color>,
+}
+
+impl MipLevel {
+    fn texel(&self, x: i64, y: i64, wrap: WrapMode) -> Color {
+        let x = address(x, self.width, wrap);
+        let y = address(y, self.height, wrap);
+        self.texels[(y * self.width + x) as usize]
+    }
+
+    fn downsample(&self) -> MipLevel {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let (x0, y0) = (2 * x as i64, 2 * y as i64);
+                let sum = self.texel(x0, y0, WrapMode::Clamp)
+                    + self.texel(x0 + 1, y0, WrapMode::Clamp)
+                    + self.texel(x0, y0 + 1, WrapMode::Clamp)
+                    + self.texel(x0 + 1, y0 + 1, WrapMode::Clamp);
+                texels.push(sum * 0.25);
+            }
+        }
+        MipLevel {
+            width,
+            height,
+            texels,
+        }
+    }
+}
+
+fn address(coord: i64, size: u32, wrap: WrapMode) -> u32 {
+    let size = size as i64;
+    let coord = match wrap {
+        WrapMode::Repeat => coord.rem_euclid(size),
+        WrapMode::Clamp => coord.clamp(0, size - 1),
+        WrapMode::Mirror => {
+            let period = coord.rem_euclid(2 * size);
+            if period < size {
+                period
+            } else {
+                2 * size - 1 - period
+            }
+        }
+    };
+    coord as u32
+}
+
+/// An image texture with a precomputed mipmap chain in linear color space. An empty image
+/// becomes a single black texel.
+pub struct ImageTexture {
+    pub wrap: WrapMode,
+    pub filter: Filter,
+    levels: Vec<MipLevel>,
+    /// Of the texels, so a debug print tells textures apart without listing them.
+    checksum: u64,
+}
+
+impl fmt::Debug for ImageTexture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ImageTexture")
+            .field("wrap", &self.wrap)
+            .field("filter", &self.filter)
+            .field("width", &self.levels[0].width)
+            .field("height", &self.levels[0].height)
+            .field("checksum", &self.checksum)
+            .finish()
+    }
+}
+
+impl ImageTexture {
+    pub fn new(image: &DynamicImage, wrap: WrapMode, filter: Filter) -> ImageTexture {
+        let texels = image
+            .pixels()
+            .map(|(_, _, p)| Color::from_rgba(p))
+            .collect();
+        ImageTexture::from_texels(image, texels, wrap, filter)
+    }
+
+    /// Load an image holding data rather than colors (normal or height maps), skipping gamma decoding.
+    pub fn new_linear(image: &DynamicImage, wrap: WrapMode, filter: Filter) -> ImageTexture {
+        let texels = image
+            .pixels()
+            .map(|(_, _, p)| Color {
+                red: p[0] as f32 / 255.0,
+                green: p[1] as f32 / 255.0,
+                blue: p[2] as f32 / 255.0,
+            })
+            .collect();
+        ImageTexture::from_texels(image, texels, wrap, filter)
+    }
+
+    fn from_texels(
+        image: &DynamicImage,
+        mut texels: Vec<Color>,
+        wrap: WrapMode,
+        filter: Filter,
+    ) -> ImageTexture {
+        let (mut width, mut height) = image.dimensions();
+        if texels.is_empty() {
+            // An empty image has nothing to look up, so stand in a single black texel
+            width = 1;
+            height = 1;
+            texels = vec![Color {
+                red: 0.0,
+                green: 0.0,
+                blue: 0.0,
+            }];
+        }
+        let checksum = utils::checksum(
+            texels
+                .iter()
+                .flat_map(|t| [t.red, t.green, t.blue])
+                .map(|v| v.to_bits() as u64),
+        );
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            texels,
+        }];
+        while let Some(last) = levels.last() {
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            let next = last.downsample();
+            levels.push(next);
+        }
+        ImageTexture {
+            wrap,
+            filter,
+            levels,
+            checksum,
+        }
+    }
+
+    pub fn sample(&self, texture_coords: &TextureCoordinates, footprint: &Footprint) -> Color {
+        match self.filter {
+            Filter::Nearest => self.nearest(texture_coords),
+            Filter::Bilinear => self.bilinear(0, texture_coords.x, texture_coords.y),
+            Filter::Trilinear => {
+                let width = axis_length(&footprint.dx).max(axis_length(&footprint.dy));
+                self.trilinear(self.level_of_detail(width), texture_coords)
+            }
+            Filter::Anisotropic { max_samples } => {
+                let (major, minor) = if axis_length(&footprint.dx) >= axis_length(&footprint.dy) {
+                    (footprint.dx, footprint.dy)
+                } else {
+                    (footprint.dy, footprint.dx)
+                };
+                let major_length = axis_length(&major);
+                let minor_length = axis_length(&minor);
+                let samples = if minor_length > 0.0 {
+                    ((major_length / minor_length).ceil() as u32).clamp(1, max_samples.max(1))
+                } else {
+                    max_samples.max(1)
+                };
+                // Each probe covers its share of the major axis, but never less than the minor one
+                let lod = self.level_of_detail(minor_length.max(major_length / samples as f32));
+
+                let mut color = Color {
+                    red: 0.0,
+                    green: 0.0,
+                    blue: 0.0,
+                };
+                for i in 0..samples {
+                    let t = (i as f32 + 0.5) / samples as f32 - 0.5;
+                    let probe = TextureCoordinates {
+                        x: texture_coords.x + major.x * t,
+                        y: texture_coords.y + major.y * t,
+                    };
+                    color = color + self.trilinear(lod, &probe);
+                }
+                color * (1.0 / samples as f32)
+            }
+        }
+    }
+
+    fn level_of_detail(&self, footprint_width: f32) -> f32 {
+        let base = &self.levels[0];
+        let texels = footprint_width * base.width.max(base.height) as f32;
+        let lod = texels.max(1.0).log2();
+        if lod.is_finite() {
+            lod.min((self.levels.len() - 1) as f32)
+        } else {
+            0.0
+        }
+    }
+
+    fn nearest(&self, texture_coords: &TextureCoordinates) -> Color {
+        let base = &self.levels[0];
+        match self.wrap {
+            // Keep the exact texel addressing of the plain image lookup
+            WrapMode::Repeat => {
+                let x = wrap(texture_coords.x, base.width);
+                let y = wrap(texture_coords.y, base.height);
+                base.texels[(y * base.width + x) as usize]
+            }
+            _ => base.texel(
+                (texture_coords.x * base.width as f32).floor() as i64,
+                (texture_coords.y * base.height as f32).floor() as i64,
+                self.wrap,
+            ),
+        }
+    }
+
+    fn bilinear(&self, level: usize, u: f32, v: f32) -> Color {
+        let level = &self.levels[level];
+        let x = u * level.width as f32 - 0.5;
+        let y = v * level.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top =
+            level.texel(x0, y0, self.wrap) * (1.0 - fx) + level.texel(x0 + 1, y0, self.wrap) * fx;
+        let bottom = level.texel(x0, y0 + 1, self.wrap) * (1.0 - fx)
+            + level.texel(x0 + 1, y0 + 1, self.wrap) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    fn trilinear(&self, lod: f32, texture_coords: &TextureCoordinates) -> Color {
+        let lower = lod.floor() as usize;
+        let upper = (lower + 1).min(self.levels.len() - 1);
+        let t = lod - lower as f32;
+        let fine = self.bilinear(lower, texture_coords.x, texture_coords.y);
+        if t == 0.0 || upper == lower {
+            return fine;
+        }
+        let coarse = self.bilinear(upper, texture_coords.x, texture_coords.y);
+        fine * (1.0 - t) + coarse * t
+    }
+}
+
+fn axis_length(axis: &TextureCoordinates) -> f32 {
+    (axis.x * axis.x + axis.y * axis.y).sqrt()
+}
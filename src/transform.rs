@@ -0,0 +1,437 @@
+use crate::error::{Error, Result};
+use crate::point::Point;
+use crate::vector3::Vector3;
+use std::ops::Mul;
+
+/// A row-major 4x4 matrix acting on column vectors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+    pub fn identity() -> Matrix4 {
+        Matrix4 {
+            m: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn translation(offset: Vector3) -> Matrix4 {
+        let mut matrix = Matrix4::identity();
+        matrix.m[0][3] = offset.x;
+        matrix.m[1][3] = offset.y;
+        matrix.m[2][3] = offset.z;
+        matrix
+    }
+
+    pub fn scaling(x: f64, y: f64, z: f64) -> Matrix4 {
+        let mut matrix = Matrix4::identity();
+        matrix.m[0][0] = x;
+        matrix.m[1][1] = y;
+        matrix.m[2][2] = z;
+        matrix
+    }
+
+    /// Rotation by `angle` radians around `axis`, following the right-hand rule.
+    pub fn rotation(axis: Vector3, angle: f64) -> Matrix4 {
+        let a = axis.normalize();
+        let (sin, cos) = angle.sin_cos();
+        let t = 1.0 - cos;
+        Matrix4 {
+            m: [
+                [
+                    t * a.x * a.x + cos,
+                    t * a.x * a.y - sin * a.z,
+                    t * a.x * a.z + sin * a.y,
+                    0.0,
+                ],
+                [
+                    t * a.x * a.y + sin * a.z,
+                    t * a.y * a.y + cos,
+                    t * a.y * a.z - sin * a.x,
+                    0.0,
+                ],
+                [
+                    t * a.x * a.z - sin * a.y,
+                    t * a.y * a.z + sin * a.x,
+                    t * a.z * a.z + cos,
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut result = Matrix4::identity();
+        for (i, row) in result.m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        result
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting. `None` for singular matrices.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inv = Matrix4::identity().m;
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))
+                .unwrap_or(column);
+            if a[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(column, pivot);
+            inv.swap(column, pivot);
+
+            let factor = 1.0 / a[column][column];
+            for j in 0..4 {
+                a[column][j] *= factor;
+                inv[column][j] *= factor;
+            }
+            for row in 0..4 {
+                if row != column {
+                    let factor = a[row][column];
+                    for j in 0..4 {
+                        a[row][j] -= factor * a[column][j];
+                        inv[row][j] -= factor * inv[column][j];
+                    }
+                }
+            }
+        }
+        Some(Matrix4 { m: inv })
+    }
+
+    pub fn transform_point(&self, p: &Point) -> Point {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == 1.0 {
+            Point { x, y, z }
+        } else {
+            Point { x, y, z } / w
+        }
+    }
+
+    /// Transform a direction, ignoring the translation.
+    pub fn transform_vector(&self, v: &Vector3) -> Vector3 {
+        let m = &self.m;
+        Vector3 {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        }
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        let mut result = Matrix4 { m: [[0.0; 4]; 4] };
+        for i in 0..4 {
+            for j in 0..4 {
+                result.m[i][j] = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        result
+    }
+}
+
+/// An invertible object-to-world transform, storing its inverse alongside.
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub matrix: Matrix4,
+    pub inverse: Matrix4,
+}
+
+impl Transform {
+    /// `None` if the matrix can't be inverted.
+    pub fn new(matrix: Matrix4) -> Option<Transform> {
+        matrix
+            .inverse()
+            .map(|inverse| Transform { matrix, inverse })
+    }
+
+    pub fn identity() -> Transform {
+        Transform {
+            matrix: Matrix4::identity(),
+            inverse: Matrix4::identity(),
+        }
+    }
+
+    pub fn translation(offset: Vector3) -> Transform {
+        Transform {
+            matrix: Matrix4::translation(offset),
+            inverse: Matrix4::translation(-offset),
+        }
+    }
+
+    /// Fails if any of the factors is zero or not finite.
+    pub fn scaling(x: f64, y: f64, z: f64) -> Result<Transform> {
+        let invertible = |f: f64| f != 0.0 && f.is_finite();
+        if !(invertible(x) && invertible(y) && invertible(z)) {
+            return Err(Error::Invalid(
+                "Scaling factors have to be finite and non-zero.".to_string(),
+            ));
+        }
+        Ok(Transform {
+            matrix: Matrix4::scaling(x, y, z),
+            inverse: Matrix4::scaling(1.0 / x, 1.0 / y, 1.0 / z),
+        })
+    }
+
+    pub fn rotation(axis: Vector3, angle: f64) -> Transform {
+        let matrix = Matrix4::rotation(axis, angle);
+        Transform {
+            matrix,
+            inverse: matrix.transpose(),
+        }
+    }
+
+    /// Apply `self` first, then `other`.
+    pub fn then(&self, other: &Transform) -> Transform {
+        Transform {
+            matrix: other.matrix * self.matrix,
+            inverse: self.inverse * other.inverse,
+        }
+    }
+
+    pub fn point_to_local(&self, p: &Point) -> Point {
+        self.inverse.transform_point(p)
+    }
+
+    pub fn point_to_world(&self, p: &Point) -> Point {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector_to_local(&self, v: &Vector3) -> Vector3 {
+        self.inverse.transform_vector(v)
+    }
+
+    pub fn vector_to_world(&self, v: &Vector3) -> Vector3 {
+        self.matrix.transform_vector(v)
+    }
+
+    /// Normals transform with the inverse transpose to stay perpendicular to the surface.
+    pub fn normal_to_world(&self, n: &Vector3) -> Vector3 {
+        self.inverse.transpose().transform_vector(n)
+    }
+}
+
+/// A rotation as a unit quaternion, for smooth interpolation between orientations.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quaternion {
+    pub fn identity() -> Quaternion {
+        Quaternion {
+            w: 1.0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    /// Rotation by `angle` radians around `axis`, following the right-hand rule.
+    pub fn from_axis_angle(axis: Vector3, angle: f64) -> Quaternion {
+        let a = axis.normalize();
+        let (sin, cos) = (angle / 2.0).sin_cos();
+        Quaternion {
+            w: cos,
+            x: a.x * sin,
+            y: a.y * sin,
+            z: a.z * sin,
+        }
+    }
+
+    fn dot(&self, other: &Quaternion) -> f64 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    fn normalize(&self) -> Quaternion {
+        let length = self.dot(self).sqrt();
+        Quaternion {
+            w: self.w / length,
+            x: self.x / length,
+            y: self.y / length,
+            z: self.z / length,
+        }
+    }
+
+    /// Spherical linear interpolation along the shorter arc.
+    pub fn slerp(&self, other: &Quaternion, t: f64) -> Quaternion {
+        let mut cos = self.dot(other);
+        let mut other = *other;
+        if cos < 0.0 {
+            cos = -cos;
+            other = Quaternion {
+                w: -other.w,
+                x: -other.x,
+                y: -other.y,
+                z: -other.z,
+            };
+        }
+        let (a, b) = if cos > 0.9995 {
+            // nearly parallel, where linear interpolation is accurate and stable
+            (1.0 - t, t)
+        } else {
+            let angle = cos.acos();
+            let sin = angle.sin();
+            (((1.0 - t) * angle).sin() / sin, (t * angle).sin() / sin)
+        };
+        Quaternion {
+            w: a * self.w + b * other.w,
+            x: a * self.x + b * other.x,
+            y: a * self.y + b * other.y,
+            z: a * self.z + b * other.z,
+        }
+        .normalize()
+    }
+
+    pub fn rotate(&self, v: Vector3) -> Vector3 {
+        let axis = Vector3 {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+        };
+        let t = axis.cross(&v) * 2.0;
+        v + t * self.w + axis.cross(&t)
+    }
+
+    pub fn transform(&self) -> Transform {
+        let sin = (1.0 - self.w * self.w).max(0.0).sqrt();
+        if sin < 1e-12 {
+            return Transform::identity();
+        }
+        let axis = Vector3 {
+            x: self.x / sin,
+            y: self.y / sin,
+            z: self.z / sin,
+        };
+        Transform::rotation(axis, 2.0 * self.w.clamp(-1.0, 1.0).acos())
+    }
+}
+
+/// A placement made of a scaling, a rotation and a translation, applied in this order.
+/// Unlike matrices, poses interpolate without shearing.
+#[derive(Clone, Copy, Debug)]
+pub struct Pose {
+    pub scale: Vector3,
+    pub rotation: Quaternion,
+    pub translation: Vector3,
+}
+
+impl Pose {
+    pub fn identity() -> Pose {
+        Pose {
+            scale: Vector3 {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+            rotation: Quaternion::identity(),
+            translation: Vector3::zero(),
+        }
+    }
+
+    pub fn translation(offset: Vector3) -> Pose {
+        Pose {
+            translation: offset,
+            ..Pose::identity()
+        }
+    }
+
+    pub fn rotation(axis: Vector3, angle: f64) -> Pose {
+        Pose {
+            rotation: Quaternion::from_axis_angle(axis, angle),
+            ..Pose::identity()
+        }
+    }
+
+    /// A zero scale factor leaves the inverse infinite, see `Scene::validate` for the poses
+    /// of moving elements.
+    pub fn transform(&self) -> Transform {
+        let Vector3 { x, y, z } = self.scale;
+        let scaling = Transform {
+            matrix: Matrix4::scaling(x, y, z),
+            inverse: Matrix4::scaling(1.0 / x, 1.0 / y, 1.0 / z),
+        };
+        scaling
+            .then(&self.rotation.transform())
+            .then(&Transform::translation(self.translation))
+    }
+
+    /// The pose a fraction `t` of the way to `other`.
+    pub fn lerp(&self, other: &Pose, t: f64) -> Pose {
+        Pose {
+            scale: self.scale * (1.0 - t) + other.scale * t,
+            rotation: self.rotation.slerp(&other.rotation, t),
+            translation: self.translation * (1.0 - t) + other.translation * t,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_identity(matrix: &Matrix4) {
+        let identity = Matrix4::identity();
+        for (row, expected) in matrix.m.iter().zip(&identity.m) {
+            for (value, expected) in row.iter().zip(expected) {
+                assert!((value - expected).abs() < 1e-12, "{:?}", matrix);
+            }
+        }
+    }
+
+    #[test]
+    fn a_matrix_times_its_inverse_is_the_identity() {
+        let axis = Vector3 {
+            x: 1.0,
+            y: 2.0,
+            z: -0.5,
+        };
+        let offset = Vector3 {
+            x: 3.0,
+            y: -1.0,
+            z: 7.0,
+        };
+        let matrix = Matrix4::translation(offset)
+            * Matrix4::rotation(axis, 0.7)
+            * Matrix4::scaling(2.0, -0.5, 3.0);
+        let inverse = matrix.inverse().unwrap();
+        assert_identity(&(matrix * inverse));
+        assert_identity(&(inverse * matrix));
+    }
+
+    #[test]
+    fn a_singular_matrix_has_no_inverse() {
+        assert_eq!(Matrix4::scaling(1.0, 0.0, 1.0).inverse(), None);
+        let mut matrix = Matrix4::rotation(
+            Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            0.3,
+        );
+        // the third row repeats the first
+        matrix.m[2] = matrix.m[0];
+        assert_eq!(matrix.inverse(), None);
+    }
+}
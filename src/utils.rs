@@ -8,6 +8,15 @@ pub fn gamma_decode(encoded: f32) -> f32 {
     encoded.powf(GAMMA)
 }
 
+/// FNV-1a over the values, so debug prints can tell large buffers apart without listing them.
+pub(crate) fn checksum(values: impl IntoIterator<Item = u64>) -> u64 {
+    values
+        .into_iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, value| {
+            (hash ^ value).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+}
+
 pub fn wrap(val: f32, bound: u32) -> u32 {
     let signed_bound = bound as i32;
     let float_coord = val * bound as f32;
@@ -18,3 +27,87 @@ pub fn wrap(val: f32, bound: u32) -> u32 {
         wrapped_coord as u32
     }
 }
+
+/// Real roots of the polynomial with `coefficients` (highest degree first) in `lo..=hi`, sorted.
+///
+/// Roots are isolated between the roots of the derivative and refined by bisection.
+pub fn polynomial_roots(coefficients: &[f64], lo: f64, hi: f64) -> Vec<f64> {
+    let degree = coefficients.len().saturating_sub(1);
+    if degree == 0 {
+        return Vec::new();
+    }
+    if coefficients[0] == 0.0 {
+        return polynomial_roots(&coefficients[1..], lo, hi);
+    }
+
+    let derivative: Vec<f64> = coefficients[..degree]
+        .iter()
+        .enumerate()
+        .map(|(i, c)| c * (degree - i) as f64)
+        .collect();
+    let mut bounds = vec![lo];
+    bounds.extend(polynomial_roots(&derivative, lo, hi));
+    bounds.push(hi);
+
+    let eval = |t: f64| coefficients.iter().fold(0.0, |acc, c| acc * t + c);
+    let mut roots: Vec<f64> = Vec::new();
+    for window in bounds.windows(2) {
+        let (mut a, mut b) = (window[0], window[1]);
+        let (fa, fb) = (eval(a), eval(b));
+        if fa == 0.0 {
+            if roots.last() != Some(&a) {
+                roots.push(a);
+            }
+            continue;
+        }
+        // a root at `b` is taken as the start of the next window, bisecting towards it
+        // would add it a second time, a little off
+        if fa.signum() == fb.signum() || fb == 0.0 {
+            continue;
+        }
+        // the polynomial is monotonic between the bounds, so the root is unique
+        for _ in 0..64 {
+            let mid = 0.5 * (a + b);
+            if eval(mid).signum() == fa.signum() {
+                a = mid;
+            } else {
+                b = mid;
+            }
+        }
+        roots.push(0.5 * (a + b));
+    }
+    if eval(hi) == 0.0 && roots.last() != Some(&hi) {
+        roots.push(hi);
+    }
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(roots: &[f64], expected: &[f64]) {
+        assert_eq!(roots.len(), expected.len(), "{:?}", roots);
+        for (root, expected) in roots.iter().zip(expected) {
+            assert!((root - expected).abs() < 1e-6, "{:?}", roots);
+        }
+    }
+
+    #[test]
+    fn finds_the_roots_of_a_quartic() {
+        // (t - 1)(t - 2)(t - 3)(t - 4)
+        let quartic = [1.0, -10.0, 35.0, -50.0, 24.0];
+        assert_roots(
+            &polynomial_roots(&quartic, 0.0, 10.0),
+            &[1.0, 2.0, 3.0, 4.0],
+        );
+        assert_roots(&polynomial_roots(&quartic, 2.5, 10.0), &[3.0, 4.0]);
+    }
+
+    #[test]
+    fn finds_a_grazing_double_root() {
+        // (t - 1)^2 (t - 3)(t + 2), touching zero at 1 like a ray grazing a torus
+        let quartic = [1.0, -3.0, -3.0, 11.0, -6.0];
+        assert_roots(&polynomial_roots(&quartic, 0.0, 10.0), &[1.0, 3.0]);
+    }
+}
@@ -47,6 +47,26 @@ impl Vector3 {
         }
     }
 
+    /// Two unit vectors perpendicular to this (normalized) vector and to each other.
+    pub fn orthonormal_basis(&self) -> (Vector3, Vector3) {
+        let helper = if self.x.abs() > 0.9 {
+            Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            }
+        } else {
+            Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            }
+        };
+        let first = self.cross(&helper).normalize();
+        let second = self.cross(&first);
+        (first, second)
+    }
+
     pub fn as_point(&self) -> Point {
         Point {
             x: self.x,
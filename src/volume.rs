@@ -0,0 +1,299 @@
+use crate::color::Color;
+use crate::error::{Error, Result};
+use crate::medium::Medium;
+use crate::point::Point;
+use crate::primitives::{slabs, AxisAlignedBox, Element, Material, SurfaceType};
+use crate::ray::Ray;
+use crate::utils;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A dense 3D grid of densities, stored with x varying fastest, then y, then z.
+pub struct VoxelGrid {
+    pub width: usize,
+    pub height: usize,
+    pub depth: usize,
+    voxels: Vec<f32>,
+    max: f32,
+    /// Of the voxels, so a debug print tells grids apart without listing them.
+    checksum: u64,
+}
+
+fn invalid(message: &str) -> Error {
+    Error::Invalid(message.to_string())
+}
+
+/// The size in bytes of a grid of voxels taking `bytes` each, if it isn't empty and fits.
+fn grid_size(width: usize, height: usize, depth: usize, bytes: usize) -> Result<usize> {
+    if width == 0 || height == 0 || depth == 0 {
+        return Err(invalid("A voxel grid can't be empty."));
+    }
+    width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(depth))
+        .and_then(|n| n.checked_mul(bytes))
+        .ok_or_else(|| invalid("The grid dimensions are too large."))
+}
+
+impl VoxelGrid {
+    /// Fails if the grid is empty or the number of voxels doesn't match the dimensions.
+    pub fn new(width: usize, height: usize, depth: usize, voxels: Vec<f32>) -> Result<VoxelGrid> {
+        if voxels.len() != grid_size(width, height, depth, 1)? {
+            return Err(invalid("The voxels don't match the grid dimensions."));
+        }
+        let max = voxels.iter().cloned().fold(0.0, f32::max);
+        let checksum = utils::checksum(voxels.iter().map(|v| v.to_bits() as u64));
+        Ok(VoxelGrid {
+            width,
+            height,
+            depth,
+            voxels,
+            max,
+            checksum,
+        })
+    }
+
+    /// Read raw little-endian 32-bit floats, x varying fastest.
+    pub fn from_raw<P: AsRef<Path>>(
+        path: P,
+        width: usize,
+        height: usize,
+        depth: usize,
+    ) -> Result<VoxelGrid> {
+        let size = grid_size(width, height, depth, 4)?;
+        let bytes = fs::read(path)?;
+        if bytes.len() != size {
+            return Err(invalid("The file size doesn't match the grid dimensions."));
+        }
+        let voxels = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        VoxelGrid::new(width, height, depth, voxels)
+    }
+
+    /// Read a three dimensional NumPy array of `f4`, `f8` or `u1` (scaled to 0..1) values.
+    /// In C order the axes are (z, y, x), in Fortran order (x, y, z).
+    pub fn from_npy<P: AsRef<Path>>(path: P) -> Result<VoxelGrid> {
+        parse_npy(&fs::read(path)?)
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.voxels[(z * self.height + y) * self.width + x]
+    }
+
+    /// Trilinearly interpolated between the voxel centers, `local` being in 0..1 over the grid.
+    pub fn sample(&self, local: &Point) -> f32 {
+        let axis = |value: f64, size: usize| {
+            let position = (value * size as f64 - 0.5).clamp(0.0, (size - 1) as f64);
+            let low = (position as usize).min(size.saturating_sub(2));
+            let high = (low + 1).min(size - 1);
+            (low, high, (position - low as f64) as f32)
+        };
+        let (x0, x1, fx) = axis(local.x, self.width);
+        let (y0, y1, fy) = axis(local.y, self.height);
+        let (z0, z1, fz) = axis(local.z, self.depth);
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let plane = |z: usize| {
+            lerp(
+                lerp(self.voxel(x0, y0, z), self.voxel(x1, y0, z), fx),
+                lerp(self.voxel(x0, y1, z), self.voxel(x1, y1, z), fx),
+                fy,
+            )
+        };
+        lerp(plane(z0), plane(z1), fz)
+    }
+
+    /// The highest density in the grid.
+    pub fn max(&self) -> f32 {
+        self.max
+    }
+}
+
+/// A voxel grid from the contents of a `.npy` file, see `VoxelGrid::from_npy`.
+fn parse_npy(bytes: &[u8]) -> Result<VoxelGrid> {
+    if bytes.len() < 10 || &bytes[..6] != b"\x93NUMPY" {
+        return Err(invalid("Not a NumPy file."));
+    }
+    let (header_start, header_length) = match bytes[6] {
+        1 => (10, u16::from_le_bytes([bytes[8], bytes[9]]) as usize),
+        _ if bytes.len() >= 12 => (
+            12,
+            u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize,
+        ),
+        _ => return Err(invalid("Truncated NumPy header.")),
+    };
+    let data_start = header_start + header_length;
+    let header = bytes
+        .get(header_start..data_start)
+        .and_then(|h| std::str::from_utf8(h).ok())
+        .ok_or_else(|| invalid("Truncated NumPy header."))?;
+
+    let descr = npy_field(header, "descr")
+        .map(|d| d.trim_matches(['\'', '"']))
+        .ok_or_else(|| invalid("NumPy header without descr."))?;
+    let fortran_order = npy_field(header, "fortran_order") == Some("True");
+    let shape: Vec<usize> = npy_field(header, "shape")
+        .ok_or_else(|| invalid("NumPy header without shape."))?
+        .trim_matches(['(', ')'])
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().map_err(|_| invalid("Invalid NumPy shape.")))
+        .collect::<Result<_>>()?;
+    if shape.len() != 3 {
+        return Err(invalid("The NumPy array has to be three dimensional."));
+    }
+    let (width, height, depth) = if fortran_order {
+        (shape[0], shape[1], shape[2])
+    } else {
+        (shape[2], shape[1], shape[0])
+    };
+
+    let data = &bytes[data_start..];
+    let count = grid_size(width, height, depth, 1)?;
+    let bytes_per_voxel = match descr {
+        "<f4" => 4,
+        "<f8" => 8,
+        "|u1" => 1,
+        _ => return Err(invalid("Unsupported NumPy data type.")),
+    };
+    if data.len() < grid_size(width, height, depth, bytes_per_voxel)? {
+        return Err(invalid("Truncated NumPy data."));
+    }
+    let voxels: Vec<f32> = match descr {
+        "<f4" => data
+            .chunks_exact(4)
+            .take(count)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        "<f8" => data
+            .chunks_exact(8)
+            .take(count)
+            .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32)
+            .collect(),
+        _ => data[..count].iter().map(|&b| b as f32 / 255.0).collect(),
+    };
+    VoxelGrid::new(width, height, depth, voxels)
+}
+
+/// The raw value of `key` in the Python dict literal of a NumPy header.
+fn npy_field<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{}'", key))? + key.len() + 2;
+    let rest = header[start..].trim_start().strip_prefix(':')?.trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')')? + 1
+    } else {
+        rest.find([',', '}'])?
+    };
+    Some(rest[..end].trim())
+}
+
+impl fmt::Debug for VoxelGrid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VoxelGrid")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("depth", &self.depth)
+            .field("max", &self.max)
+            .field("checksum", &self.checksum)
+            .finish()
+    }
+}
+
+/// A voxel grid stretched over the box from `min` to `max`, zero outside of it.
+#[derive(Clone, Debug)]
+pub struct DensityField {
+    pub grid: Arc<VoxelGrid>,
+    pub min: Point,
+    pub max: Point,
+}
+
+impl DensityField {
+    pub fn density(&self, p: &Point) -> f32 {
+        let size = self.max - self.min;
+        let local = Point {
+            x: (p.x - self.min.x) / size.x,
+            y: (p.y - self.min.y) / size.y,
+            z: (p.z - self.min.z) / size.z,
+        };
+        let inside = |v: f64| (0.0..=1.0).contains(&v);
+        if inside(local.x) && inside(local.y) && inside(local.z) {
+            self.grid.sample(&local)
+        } else {
+            0.0
+        }
+    }
+
+    /// The part of the ray inside the box, clipped to start at the ray origin.
+    pub fn span(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let (enter, exit) = slabs(&self.min, &self.max, ray)?;
+        if exit < 0.0 {
+            None
+        } else {
+            Some((enter.max(0.0), exit))
+        }
+    }
+}
+
+/// An invisible box holding a smoke or cloud volume. The grid values are scaled by `density`,
+/// `albedo` is the scattered fraction of the light interacting with the volume, and `emission`
+/// the light it gives off per unit of density, like in fire.
+pub fn voxel_volume(field: DensityField, density: f32, albedo: Color, emission: Color) -> Element {
+    Element::Box(AxisAlignedBox {
+        min: field.min,
+        max: field.max,
+        material: Material {
+            surface: SurfaceType::Boundary,
+            medium: Some(Medium::voxels(field, density, albedo, emission)),
+            ..Default::default()
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A version 1 file of a 2x2x2 array of `descr` values, each taking `size` bytes.
+    fn npy(descr: &str, size: usize) -> Vec<u8> {
+        let header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': (2, 2, 2), }}\n",
+            descr
+        );
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.bytes());
+        bytes.extend(vec![0; 8 * size]);
+        bytes
+    }
+
+    fn rejected(bytes: &[u8], message: &str) -> bool {
+        matches!(parse_npy(bytes), Err(Error::Invalid(m)) if m == message)
+    }
+
+    #[test]
+    fn reads_a_little_endian_array() {
+        let grid = parse_npy(&npy("<f4", 4)).unwrap();
+        assert_eq!((grid.width, grid.height, grid.depth), (2, 2, 2));
+    }
+
+    #[test]
+    fn rejects_a_truncated_header() {
+        let bytes = npy("<f4", 4);
+        assert!(rejected(&bytes[..8], "Not a NumPy file."));
+        assert!(rejected(&bytes[..30], "Truncated NumPy header."));
+        // version 2 has a 4 byte header length
+        let mut version_2 = bytes[..11].to_vec();
+        version_2[6] = 2;
+        assert!(rejected(&version_2, "Truncated NumPy header."));
+    }
+
+    #[test]
+    fn rejects_a_big_endian_array() {
+        assert!(rejected(&npy(">f4", 4), "Unsupported NumPy data type."));
+        assert!(rejected(&npy(">f8", 8), "Unsupported NumPy data type."));
+    }
+}
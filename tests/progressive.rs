@@ -0,0 +1,182 @@
+use rays::color::{Color, Colorization};
+use rays::filter::{FilterKind, PixelFilter};
+use rays::integrator::Integrator;
+use rays::light::{Light, SphericalLight};
+use rays::motion::Shutter;
+use rays::point::Point;
+use rays::primitives::{Element, Material, Plane, Sphere};
+use rays::render::{render_progressive, CancellationToken, Progressive, Sampling};
+use rays::sampler::Sampler;
+use rays::scene::Scene;
+use rays::transform::Quaternion;
+use rays::vector3::Vector3;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+fn material(red: f32, green: f32, blue: f32) -> Material {
+    Material {
+        color: Colorization::Color(Color { red, green, blue }),
+        albedo: 0.8,
+        ..Default::default()
+    }
+}
+
+/// Path traced, so every sample differs, and taller than a band of rows, with a filter
+/// splatting the samples into the neighbouring rows.
+fn scene() -> Scene {
+    Scene {
+        width: 48,
+        height: 72,
+        origin: Point {
+            x: 0.0,
+            y: 0.5,
+            z: 1.0,
+        },
+        fov: 60.0,
+        orientation: Quaternion::identity(),
+        elements: vec![
+            Element::Plane(Plane {
+                p: Point {
+                    x: 0.0,
+                    y: -0.5,
+                    z: 0.0,
+                },
+                normal: Vector3 {
+                    x: 0.0,
+                    y: -1.0,
+                    z: 0.0,
+                },
+                material: material(0.8, 0.8, 0.8),
+            }),
+            Element::Sphere(Sphere {
+                center: Point {
+                    x: 0.0,
+                    y: 0.0,
+                    z: -3.0,
+                },
+                radius: 0.5,
+                material: material(0.8, 0.2, 0.2),
+            }),
+        ],
+        lights: vec![Light::Spherical(SphericalLight {
+            position: Point {
+                x: 2.0,
+                y: -5.0,
+                z: -1.0,
+            },
+            color: Color {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            intensity: 2000.0,
+        })],
+        shadow_bias: 1e-6,
+        max_recursion: 3,
+        medium: None,
+        integrator: Integrator::PathTracing,
+        shutter: Shutter::default(),
+        sampler: Sampler::Independent,
+        sampling: Sampling::default(),
+        filter: PixelFilter::new(FilterKind::Gaussian),
+        crop: None,
+        post: Vec::new(),
+        seed: 7,
+    }
+}
+
+fn checkpoint_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rays-{}-{}.ckpt", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn progressive(max_samples: u32, time: Option<Duration>, path: &Path) -> Progressive {
+    Progressive {
+        time,
+        max_samples: Some(max_samples),
+        checkpoint_path: Some(path.to_path_buf()),
+        ..Default::default()
+    }
+}
+
+/// The final checkpoint of an uninterrupted render, holding the raw sums of the pixels.
+fn uninterrupted(scene: &Scene, max_samples: u32) -> Vec<u8> {
+    let path = checkpoint_path("uninterrupted");
+    render_progressive(
+        scene,
+        &progressive(max_samples, None, &path),
+        &CancellationToken::new(),
+    )
+    .unwrap();
+    let bytes = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    bytes
+}
+
+#[test]
+fn resuming_after_a_sample_limit_matches_an_uninterrupted_render() {
+    let scene = scene();
+    let path = checkpoint_path("samples");
+    render_progressive(
+        &scene,
+        &progressive(4, None, &path),
+        &CancellationToken::new(),
+    )
+    .unwrap();
+    render_progressive(
+        &scene,
+        &progressive(8, None, &path),
+        &CancellationToken::new(),
+    )
+    .unwrap();
+    let resumed = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(resumed == uninterrupted(&scene, 8));
+}
+
+#[test]
+fn resuming_after_a_time_limit_matches_an_uninterrupted_render() {
+    let scene = scene();
+    let path = checkpoint_path("time");
+    // passes take a few milliseconds, so most of these stop in the middle of one
+    for time in [1, 12, 20, 28].iter() {
+        let limit = Some(Duration::from_millis(*time));
+        render_progressive(
+            &scene,
+            &progressive(12, limit, &path),
+            &CancellationToken::new(),
+        )
+        .unwrap();
+    }
+    render_progressive(
+        &scene,
+        &progressive(12, None, &path),
+        &CancellationToken::new(),
+    )
+    .unwrap();
+    let resumed = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(resumed == uninterrupted(&scene, 12));
+}
+
+#[test]
+fn resuming_after_a_cancellation_matches_an_uninterrupted_render() {
+    let scene = scene();
+    let path = checkpoint_path("cancelled");
+    let cancellation = CancellationToken::new();
+    cancellation.cancel();
+    // the first pass is finished anyway, and checkpointed
+    render_progressive(&scene, &progressive(12, None, &path), &cancellation).unwrap();
+    assert!(path.exists());
+    render_progressive(
+        &scene,
+        &progressive(12, None, &path),
+        &CancellationToken::new(),
+    )
+    .unwrap();
+    let resumed = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(resumed == uninterrupted(&scene, 12));
+}
@@ -0,0 +1,119 @@
+use rayon::ThreadPoolBuilder;
+use rays::color::{Color, Colorization};
+use rays::filter::{FilterKind, PixelFilter};
+use rays::integrator::Integrator;
+use rays::light::{Light, SphericalLight};
+use rays::motion::Shutter;
+use rays::point::Point;
+use rays::primitives::{Element, Material, Plane, Sphere, SurfaceType};
+use rays::render::{render, Sampling};
+use rays::sampler::Sampler;
+use rays::scene::Scene;
+use rays::texture::Scalar;
+use rays::transform::Quaternion;
+use rays::vector3::Vector3;
+
+fn material(red: f32, green: f32, blue: f32) -> Material {
+    Material {
+        color: Colorization::Color(Color { red, green, blue }),
+        albedo: 0.8,
+        ..Default::default()
+    }
+}
+
+/// Path traced with adaptive sampling, so pixels take different numbers of samples, and
+/// taller than a band of rows, with a filter splatting the samples into the neighbouring rows.
+fn scene() -> Scene {
+    Scene {
+        width: 48,
+        height: 72,
+        origin: Point {
+            x: 0.0,
+            y: 0.5,
+            z: 1.0,
+        },
+        fov: 60.0,
+        orientation: Quaternion::identity(),
+        elements: vec![
+            Element::Plane(Plane {
+                p: Point {
+                    x: 0.0,
+                    y: -0.5,
+                    z: 0.0,
+                },
+                normal: Vector3 {
+                    x: 0.0,
+                    y: -1.0,
+                    z: 0.0,
+                },
+                material: material(0.8, 0.8, 0.8),
+            }),
+            Element::Sphere(Sphere {
+                center: Point {
+                    x: -0.6,
+                    y: 0.0,
+                    z: -3.0,
+                },
+                radius: 0.5,
+                material: material(0.8, 0.2, 0.2),
+            }),
+            Element::Sphere(Sphere {
+                center: Point {
+                    x: 0.6,
+                    y: 0.0,
+                    z: -3.0,
+                },
+                radius: 0.5,
+                material: Material {
+                    surface: SurfaceType::Reflective {
+                        reflectivity: 0.8,
+                        roughness: Scalar::Constant(0.3),
+                    },
+                    ..material(0.8, 0.8, 0.8)
+                },
+            }),
+        ],
+        lights: vec![Light::Spherical(SphericalLight {
+            position: Point {
+                x: 2.0,
+                y: -5.0,
+                z: -1.0,
+            },
+            color: Color {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            intensity: 2000.0,
+        })],
+        shadow_bias: 1e-6,
+        max_recursion: 3,
+        medium: None,
+        integrator: Integrator::PathTracing,
+        shutter: Shutter::default(),
+        sampler: Sampler::Sobol,
+        sampling: Sampling::adaptive(4, 32, 0.05),
+        filter: PixelFilter::new(FilterKind::Gaussian),
+        crop: None,
+        post: Vec::new(),
+        seed: 7,
+    }
+}
+
+fn render_with_threads(scene: &Scene, threads: usize) -> Vec<u8> {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .unwrap();
+    pool.install(|| render(scene).unwrap().to_rgba8().into_raw())
+}
+
+#[test]
+fn the_image_is_the_same_for_any_number_of_threads() {
+    let scene = scene();
+    let single = render_with_threads(&scene, 1);
+    let default = render(&scene).unwrap().to_rgba8().into_raw();
+    assert!(single == default);
+    // the default pool has a single thread on a single core
+    assert!(single == render_with_threads(&scene, 5));
+}